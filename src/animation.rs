@@ -0,0 +1,131 @@
+// Pyxel Edit tile animations.
+//
+// LDtk can't play animated tiles: the animations are exported with their frames in the tileset
+// images (for a sidecar file read by the game engine), and optionally as an LDtk enum.
+
+use serde::Serialize;
+use crate::ldtk::{EnumDefinition, EnumValueDefinition, Ldtk};
+use crate::pyxel::PyxelDoc;
+use crate::tileset::{page_name, SharedData};
+use crate::uid::UidAllocator;
+
+/// A Pyxel Edit animation, with its frames in the tileset images
+#[derive(Serialize, Clone, Debug)]
+pub struct TileAnimation {
+    pub name: String,
+    /// Pyxel Edit document of the animation
+    pub document: String,
+    pub frames: Vec<AnimationFrame>,
+}
+
+/// An animation frame
+#[derive(Serialize, Clone, Debug)]
+pub struct AnimationFrame {
+    /// Identifier of the LDtk tileset holding the frame tile
+    pub tileset: String,
+    /// Tile id in the tileset
+    #[serde(rename = "tileId")]
+    pub tile_id: i64,
+    /// Pixel coordinates (`[x,y]`) of the tile in the tileset image
+    pub src: Vec<i64>,
+    /// LDtk flip bits (X=1, Y=2) to apply to the tile
+    pub flip: i64,
+    /// Frame duration in milliseconds
+    pub duration: i64,
+}
+
+/// Animations of the pyxel documents. Animations using missing tiles are left out with a warning.
+pub fn tile_animations(docs: &[&PyxelDoc], data: &SharedData, warnings: &mut Vec<String>) -> Vec<TileAnimation> {
+    let mut animations = vec![];
+    for (doc, doc_tiles) in docs.iter().zip(data.docs.iter()) {
+        'animation: for animation in doc.animations.values() {
+            let mut frames = vec![];
+            for frame in 0..animation.length.max(0) {
+                let index = animation.base_tile + frame;
+                let tile = match doc_tiles.tile_ids.get(&index) {
+                    Some(tile) => tile,
+                    None => {
+                        warnings.push(format!(
+                            "'{}': animation '{}' left out (tile{}.png is missing)",
+                            doc.name, animation.name, index
+                        ));
+                        continue 'animation;
+                    }
+                };
+                // multipliers are in percent of the base duration
+                let multiplier = animation.frame_duration_multipliers.get(frame as usize).cloned().unwrap_or(100);
+                frames.push(AnimationFrame {
+                    tileset: page_name(&data.tileset_filename, data.page_of(tile.tile_id)),
+                    tile_id: data.page_tile_id(tile.tile_id),
+                    src: data.tile_src(tile.tile_id),
+                    flip: tile.flip,
+                    duration: animation.frame_duration * multiplier / 100,
+                });
+            }
+            animations.push(TileAnimation {
+                name: animation.name.to_owned(),
+                document: doc.name.to_owned(),
+                frames,
+            });
+        }
+    }
+    animations
+}
+
+/// Add an enum with one value per animation to the project (or replace the enum with the same
+/// identifier). The first frame of each animation is the value icon.
+pub fn merge_animation_enum(
+    ldtk: &mut Ldtk,
+    uids: &mut UidAllocator,
+    identifier: &str,
+    animations: &[TileAnimation],
+    tile_size: i64,
+) {
+    let existing_uid = ldtk.defs.enums.iter().find(|def| def.identifier == identifier).map(|def| def.uid);
+    let uid = existing_uid.unwrap_or_else(|| uids.alloc());
+
+    // an enum has a single icon tileset, the one of the first animation
+    let icon_tileset = animations.iter().flat_map(|animation| animation.frames.first()).next();
+    let icon_tileset_uid = icon_tileset.and_then(|frame| {
+        ldtk.defs.tilesets.iter().find(|def| def.identifier == frame.tileset).map(|def| def.uid)
+    });
+
+    let mut values: Vec<EnumValueDefinition> = vec![];
+    for animation in animations.iter() {
+        let mut id = enum_value_id(&animation.name);
+        let base_id = id.to_owned();
+        let mut suffix = 1;
+        while values.iter().any(|value| value.id == id) {
+            suffix += 1;
+            id = format!("{}_{}", base_id, suffix);
+        }
+        let icon = animation.frames.first().filter(|frame| Some(&frame.tileset) == icon_tileset.map(|icon| &icon.tileset));
+        values.push(EnumValueDefinition {
+            tile_src_rect: icon.map_or(vec![], |frame| vec![frame.src[0], frame.src[1], tile_size, tile_size]),
+            id,
+            tile_id: icon.map(|frame| frame.tile_id),
+        });
+    }
+
+    let enum_def = EnumDefinition {
+        external_file_checksum: Option::None,
+        external_rel_path: Option::None,
+        icon_tileset_uid,
+        identifier: identifier.to_owned(),
+        uid,
+        values,
+    };
+    match ldtk.defs.enums.iter_mut().find(|def| def.uid == uid) {
+        Some(existing) => *existing = enum_def,
+        None => ldtk.defs.enums.push(enum_def),
+    }
+}
+
+/// LDtk enum values are identifiers: letters, digits and underscores, not starting with a digit
+fn enum_value_id(name: &str) -> String {
+    let id: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    match id.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => id,
+        _ => format!("_{}", id),
+    }
+}
@@ -0,0 +1,286 @@
+// Command line arguments of the pyxeledit2ldtk executable.
+
+use std::path::PathBuf;
+use pyxeledit2ldtk::ldtk::WorldLayout;
+use pyxeledit2ldtk::{LayerGroups, PaletteFormat, TileDedupe};
+
+pub const USAGE: &str = "\
+Convert Pyxel Edit (.pyxel) files to a LDtk project and its tileset image.
+Several files (or a directory of .pyxel files) are converted into a single project,
+one level per file, sharing the same tileset.
+
+USAGE:
+    pyxeledit2ldtk [OPTIONS] <source.pyxel|directory>...
+
+OPTIONS:
+    -c, --config <file.json>        Config file with the project defaults, layer settings and
+                                    output paths (default: pyxeledit2ldtk.json next to the
+                                    first source, if any)
+    -o, --out-dir <dir>             Output directory (default: current directory)
+        --ldtk-name <name>          LDtk file name, without extension (default: source name,
+                                    or \"world\" for several sources)
+        --tileset-name <name>       Tileset identifier and image name, without extension
+                                    (default: the LDtk file name)
+        --tileset-rel-path <path>   Tileset image path written in the LDtk file, relative to
+                                    the LDtk file (default: <tileset-name>.png)
+        --layout <layout>           World layout of the levels: free, gridvania, horizontal,
+                                    vertical (default: free)
+        --dedupe <mode>             Store duplicated tiles once in the tileset image: none, exact,
+                                    flipped (also flipped/180° rotated copies)
+                                    (default: exact for several sources, none otherwise)
+        --padding <px>              Space between the tileset image borders and the tiles (default: 0)
+        --spacing <px>              Space between two tiles of the tileset image (default: 0)
+        --extrude <px>              Repeat the tile edges around each tile, against texture bleeding
+                                    (added to the padding and spacing, default: 0)
+        --pot                       Use power-of-two sizes for the tileset image
+        --max-atlas-size <px>       Maximum width and height of the tileset image
+        --split-atlas               Split the tileset into several images when the tiles don't fit
+                                    in --max-atlas-size (one LDtk tileset each, layers using
+                                    several images are split into one layer per image)
+        --skip-hidden               Leave out the layers hidden or muted in Pyxel Edit
+        --groups <mode>             Layers in Pyxel Edit groups: flatten (keep the layer names),
+                                    prefix (\"Group/Layer\" names), collapse (one layer per group)
+                                    (default: flatten)
+        --group-inherit             Layers in a hidden, muted or semi-transparent group are
+                                    hidden, muted or semi-transparent too
+        --pixel-layers              Also convert the pixels painted in the layers outside of tiles,
+                                    cut into new tiles
+        --animation-enum <name>     Add a LDtk enum with one value per Pyxel Edit animation
+        --palette <format>          Write the Pyxel Edit palette to <ldtk-name>.<format>:
+                                    gpl (GIMP), pal (JASC), hex (one color per line)
+        --palette-int-grid <name>   Add a IntGrid layer with one value per palette color (value N
+                                    has the color of the palette slot N, counted from 1)
+        --collision <file.json>     Add a IntGrid collision layer, with the tile index -> value
+                                    mapping of a JSON file (see README)
+        --collision-layer <name>    Pyxel layer painted with collision tiles, converted into the
+                                    IntGrid collision layer instead of a tile layer (Group/Layer
+                                    in a group with --groups prefix)
+        --entities <file.json>      Convert the tiles of the entity layer into entities, with the
+                                    tile index -> entity mapping of a JSON file (see README)
+        --entity-layer <name>       Pyxel layer painted with entity tiles (default: entities),
+                                    Group/Layer in a group with --groups prefix
+    -m, --merge                     Merge into the existing LDtk file instead of overwriting it:
+                                    only the converted levels, their layers and the tileset
+                                    are replaced
+    -f, --force                     Overwrite existing output files
+    -w, --watch                     Keep running and convert again whenever a source changes
+        --poll-interval <ms>        Delay between two checks of the sources in watch mode
+                                    (default: 500)
+    -q, --quiet                     Only print errors
+    -v, --verbose                   Print conversion details
+    -h, --help                      Print this help
+    -V, --version                   Print version";
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+pub struct Args {
+    pub sources: Vec<PathBuf>,
+    pub config_file: Option<PathBuf>,
+    /// None for the config file one (or the current directory)
+    pub out_dir: Option<PathBuf>,
+    pub ldtk_name: Option<String>,
+    pub tileset_name: Option<String>,
+    pub tileset_rel_path: Option<String>,
+    pub world_layout: WorldLayout,
+    pub tile_dedupe: Option<TileDedupe>,
+    pub padding: u32,
+    pub spacing: u32,
+    pub extrude: u32,
+    pub power_of_two: bool,
+    pub max_atlas_size: Option<u32>,
+    pub split_atlas: bool,
+    pub skip_hidden_layers: bool,
+    pub layer_groups: LayerGroups,
+    pub inherit_group_visibility: bool,
+    pub pixel_layers: bool,
+    pub animation_enum: Option<String>,
+    pub palette_formats: Vec<PaletteFormat>,
+    pub palette_int_grid: Option<String>,
+    pub collision_file: Option<PathBuf>,
+    pub collision_layer: Option<String>,
+    pub entity_file: Option<PathBuf>,
+    pub entity_layer: Option<String>,
+    pub merge: bool,
+    pub force: bool,
+    pub watch: bool,
+    /// delay between two checks of the sources, in milliseconds
+    pub poll_interval: u64,
+    pub verbosity: Verbosity,
+}
+
+pub enum Command {
+    Convert(Box<Args>),
+    Help,
+    Version,
+}
+
+/// Parse the command line arguments (without the executable name)
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut sources: Vec<PathBuf> = vec![];
+    let mut config_file = None;
+    let mut out_dir = None;
+    let mut ldtk_name = None;
+    let mut tileset_name = None;
+    let mut tileset_rel_path = None;
+    let mut world_layout = WorldLayout::Free;
+    let mut tile_dedupe = None;
+    let mut padding = 0;
+    let mut spacing = 0;
+    let mut extrude = 0;
+    let mut power_of_two = false;
+    let mut max_atlas_size = None;
+    let mut split_atlas = false;
+    let mut skip_hidden_layers = false;
+    let mut layer_groups = LayerGroups::Flatten;
+    let mut inherit_group_visibility = false;
+    let mut pixel_layers = false;
+    let mut animation_enum = None;
+    let mut palette_formats = vec![];
+    let mut palette_int_grid = None;
+    let mut collision_file = None;
+    let mut collision_layer = None;
+    let mut entity_file = None;
+    let mut entity_layer = None;
+    let mut merge = false;
+    let mut force = false;
+    let mut watch = false;
+    let mut poll_interval = 500;
+    let mut verbosity = Verbosity::Normal;
+
+    while let Some(arg) = args.next() {
+        // accept both "--option value" and "--option=value"
+        let (name, inline_value) = match arg.find('=') {
+            Some(pos) if arg.starts_with("--") => (arg[..pos].to_owned(), Some(arg[pos + 1..].to_owned())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || match inline_value.clone().or_else(|| args.next()) {
+            Some(value) => Ok(value),
+            None => Err(format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-c" | "--config" => config_file = Some(PathBuf::from(value()?)),
+            "-o" | "--out-dir" => out_dir = Some(PathBuf::from(value()?)),
+            "--ldtk-name" => ldtk_name = Some(value()?),
+            "--tileset-name" => tileset_name = Some(value()?),
+            "--tileset-rel-path" => tileset_rel_path = Some(value()?),
+            "--layout" => world_layout = parse_layout(&value()?)?,
+            "--dedupe" => tile_dedupe = Some(parse_dedupe(&value()?)?),
+            "--padding" => padding = parse_pixels(&name, &value()?)?,
+            "--spacing" => spacing = parse_pixels(&name, &value()?)?,
+            "--extrude" => extrude = parse_pixels(&name, &value()?)?,
+            "--pot" => power_of_two = true,
+            "--max-atlas-size" => max_atlas_size = Some(parse_pixels(&name, &value()?)?),
+            "--split-atlas" => split_atlas = true,
+            "--skip-hidden" => skip_hidden_layers = true,
+            "--groups" => layer_groups = parse_groups(&value()?)?,
+            "--group-inherit" => inherit_group_visibility = true,
+            "--pixel-layers" => pixel_layers = true,
+            "--animation-enum" => animation_enum = Some(value()?),
+            "--palette" => palette_formats.push(parse_palette_format(&value()?)?),
+            "--palette-int-grid" => palette_int_grid = Some(value()?),
+            "--collision" => collision_file = Some(PathBuf::from(value()?)),
+            "--collision-layer" => collision_layer = Some(value()?),
+            "--entities" => entity_file = Some(PathBuf::from(value()?)),
+            "--entity-layer" => entity_layer = Some(value()?),
+            "-m" | "--merge" => merge = true,
+            "-f" | "--force" => force = true,
+            "-w" | "--watch" => watch = true,
+            "--poll-interval" => {
+                let ms = value()?;
+                poll_interval = match ms.parse() {
+                    Ok(ms) if ms > 0 => ms,
+                    _ => return Err(format!("invalid poll interval {}", ms)),
+                };
+            }
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            _ if name.starts_with('-') => return Err(format!("unknown option {}", name)),
+            _ => sources.push(PathBuf::from(arg)),
+        }
+    }
+
+    if sources.is_empty() {
+        return Err("no source file".to_owned());
+    }
+    Ok(Command::Convert(Box::new(Args {
+        sources,
+        config_file,
+        out_dir,
+        ldtk_name,
+        tileset_name,
+        tileset_rel_path,
+        world_layout,
+        tile_dedupe,
+        padding,
+        spacing,
+        extrude,
+        power_of_two,
+        max_atlas_size,
+        split_atlas,
+        skip_hidden_layers,
+        layer_groups,
+        inherit_group_visibility,
+        pixel_layers,
+        animation_enum,
+        palette_formats,
+        palette_int_grid,
+        collision_file,
+        collision_layer,
+        entity_file,
+        entity_layer,
+        merge,
+        force,
+        watch,
+        poll_interval,
+        verbosity,
+    })))
+}
+
+fn parse_layout(value: &str) -> Result<WorldLayout, String> {
+    match value.to_lowercase().as_str() {
+        "free" => Ok(WorldLayout::Free),
+        "gridvania" => Ok(WorldLayout::GridVania),
+        "horizontal" | "linearhorizontal" => Ok(WorldLayout::LinearHorizontal),
+        "vertical" | "linearvertical" => Ok(WorldLayout::LinearVertical),
+        _ => Err(format!("unknown layout {}", value)),
+    }
+}
+
+fn parse_dedupe(value: &str) -> Result<TileDedupe, String> {
+    match value.to_lowercase().as_str() {
+        "none" => Ok(TileDedupe::None),
+        "exact" => Ok(TileDedupe::Exact),
+        "flipped" => Ok(TileDedupe::Flipped),
+        _ => Err(format!("unknown dedupe mode {}", value)),
+    }
+}
+
+fn parse_groups(value: &str) -> Result<LayerGroups, String> {
+    match value.to_lowercase().as_str() {
+        "flatten" => Ok(LayerGroups::Flatten),
+        "prefix" => Ok(LayerGroups::Prefix),
+        "collapse" => Ok(LayerGroups::Collapse),
+        _ => Err(format!("unknown groups mode {}", value)),
+    }
+}
+
+fn parse_palette_format(value: &str) -> Result<PaletteFormat, String> {
+    match value.to_lowercase().as_str() {
+        "gpl" | "gimp" => Ok(PaletteFormat::Gpl),
+        "pal" | "jasc" => Ok(PaletteFormat::Pal),
+        "hex" => Ok(PaletteFormat::Hex),
+        _ => Err(format!("unknown palette format {}", value)),
+    }
+}
+
+fn parse_pixels(name: &str, value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, name))
+}
@@ -0,0 +1,119 @@
+// IntGrid collision layer.
+//
+// The collision values come from the tiles: a mapping from pyxel tile index to IntGrid value,
+// applied to the tiles of the converted layers, or to a dedicated pyxel layer painted with
+// collision tiles.
+
+use serde::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::ldtk::IntGridValueDefinition;
+use crate::palette::{int_grid_value, PaletteColor};
+use crate::pyxel::Layer;
+
+/// Collision settings, usually read from a JSON sidecar file:
+///
+/// ```json
+/// {
+///   "layer": "Collisions",
+///   "values": [ { "value": 1, "identifier": "solid", "color": "#ff0000" } ],
+///   "tiles": { "12": 1, "13": 1, "40": 2 },
+///   "sourceLayer": "collision"
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CollisionMap {
+    /// Identifier of the generated IntGrid layer
+    #[serde(default = "default_layer")]
+    pub layer: String,
+    /// IntGrid values. Defaults to the values used in `tiles` (and 1 with a source layer)
+    #[serde(default)]
+    pub values: Vec<CollisionValue>,
+    /// Pyxel tile index -> IntGrid value
+    #[serde(default)]
+    pub tiles: BTreeMap<i64, i64>,
+    /// Name of the layer holding the collisions, after the groups are resolved (`Group/Layer`
+    /// with `LayerGroups::Prefix`). Its tiles use the `tiles` values (1 for unlisted tiles)
+    /// and it is not converted as a tile layer.
+    /// Without it, `tiles` is applied to the tiles of every converted layer.
+    #[serde(rename = "sourceLayer", default)]
+    pub source_layer: Option<String>,
+}
+
+/// An IntGrid value of the collision layer
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CollisionValue {
+    pub value: i64,
+    #[serde(default)]
+    pub identifier: Option<String>,
+    /// `#rrggbb` color, defaults to the color of the palette slot with the same number (see
+    /// `ConvertOptions::palette_int_grid`)
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+fn default_layer() -> String {
+    "Collisions".to_owned()
+}
+
+impl Default for CollisionMap {
+    fn default() -> CollisionMap {
+        CollisionMap {
+            layer: default_layer(),
+            values: vec![],
+            tiles: BTreeMap::new(),
+            source_layer: Option::None,
+        }
+    }
+}
+
+impl CollisionMap {
+    /// Whether a pyxel layer is the collision source layer
+    pub fn is_source_layer(&self, layer: &Layer) -> bool {
+        self.source_layer.as_ref() == Some(&layer.name)
+    }
+
+    /// IntGrid value definitions of the collision layer
+    pub fn int_grid_values(&self, palette: &[PaletteColor]) -> Vec<IntGridValueDefinition> {
+        let mut values = self.values.to_owned();
+        if values.is_empty() {
+            let mut used: BTreeSet<i64> = self.tiles.values().cloned().filter(|&value| value > 0).collect();
+            if self.source_layer.is_some() {
+                used.insert(1);
+            }
+            values = used.into_iter().map(|value| CollisionValue { value, identifier: None, color: None }).collect();
+        }
+        values
+            .into_iter()
+            .map(|value| {
+                let palette_color = palette.iter().find(|color| int_grid_value(color) == value.value);
+                IntGridValueDefinition {
+                    color: value.color.unwrap_or_else(|| palette_color.map_or("#000000".to_owned(), |color| color.hex())),
+                    identifier: value.identifier,
+                    value: value.value,
+                }
+            })
+            .collect()
+    }
+
+    /// IntGrid values of a level (`map_w * map_h` cells, 0 for empty cells).
+    /// `doc_layers` are the tile layers of the document with the groups resolved, `layers` the
+    /// converted ones, top-most first: the upper tiles win.
+    pub fn int_grid_csv(&self, doc_layers: &[Layer], layers: &[&Layer], map_w: i64, map_h: i64) -> Vec<i64> {
+        let mut csv = vec![0; (map_w * map_h).max(0) as usize];
+        let sources: Vec<&Layer> = match &self.source_layer {
+            Some(_) => doc_layers.iter().filter(|layer| self.is_source_layer(layer)).collect(),
+            None => layers.to_vec(),
+        };
+        let unlisted = if self.source_layer.is_some() { 1 } else { 0 };
+        for layer in sources.into_iter() {
+            for (&cell, tile_ref) in layer.tile_refs.iter() {
+                let value = self.tiles.get(&tile_ref.index).cloned().unwrap_or(unlisted);
+                match csv.get_mut(cell as usize) {
+                    Some(csv_value) if *csv_value == 0 && value > 0 => *csv_value = value,
+                    _ => {}
+                }
+            }
+        }
+        csv
+    }
+}
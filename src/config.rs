@@ -0,0 +1,157 @@
+// Conversion config file.
+//
+// A JSON file kept next to the .pyxel sources with the settings of a project: the defaults of
+// new LDtk projects, how each pyxel layer is converted, and where the outputs are written.
+
+use serde::*;
+use std::collections::BTreeMap;
+use crate::collision::CollisionMap;
+use crate::convert::LDTK_JSON_VERSION;
+use crate::entity::EntityMap;
+use crate::ldtk::Type;
+
+/// Name of the config file looked up next to the sources
+pub const CONFIG_FILE_NAME: &str = "pyxeledit2ldtk.json";
+
+/// Content of a config file:
+///
+/// ```json
+/// {
+///   "project": { "bgColor": "#202020", "defaultLevelWidth": 320 },
+///   "layers": {
+///     "Layer 0": { "rename": "Ground" },
+///     "sketch": { "skip": true },
+///     "walls": { "type": "IntGrid", "values": { "12": 1, "40": 2 } },
+///     "entities": { "type": "Entities", "entities": { "64": "PlayerStart" } }
+///   },
+///   "output": { "outDir": "../levels", "ldtkName": "world" }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub project: ProjectSettings,
+    /// Layer name -> layer settings. The names are the ones of the layers with the groups
+    /// resolved (`Group/Layer` with `LayerGroups::Prefix`, the group name with `Collapse`)
+    #[serde(default)]
+    pub layers: BTreeMap<String, LayerSettings>,
+    #[serde(default)]
+    pub output: OutputSettings,
+}
+
+/// Settings of the LDtk projects created by the converter (a merged project keeps its own)
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProjectSettings {
+    #[serde(rename = "backupLimit")]
+    pub backup_limit: i64,
+    #[serde(rename = "backupOnSave")]
+    pub backup_on_save: bool,
+    #[serde(rename = "bgColor")]
+    pub bg_color: String,
+    #[serde(rename = "defaultLevelBgColor")]
+    pub default_level_bg_color: String,
+    #[serde(rename = "defaultLevelWidth")]
+    pub default_level_width: i64,
+    #[serde(rename = "defaultLevelHeight")]
+    pub default_level_height: i64,
+    #[serde(rename = "worldGridWidth")]
+    pub world_grid_width: i64,
+    #[serde(rename = "worldGridHeight")]
+    pub world_grid_height: i64,
+    /// LDtk version written in the project
+    #[serde(rename = "jsonVersion")]
+    pub json_version: String,
+    /// Random seed of the layer instances (used by LDtk auto-layer rules)
+    #[serde(rename = "layerSeed")]
+    pub layer_seed: i64,
+}
+
+impl Default for ProjectSettings {
+    fn default() -> ProjectSettings {
+        ProjectSettings {
+            backup_limit: 3,
+            backup_on_save: true,
+            bg_color: "#000000".to_owned(),
+            default_level_bg_color: "#333333".to_owned(),
+            default_level_width: 256,
+            default_level_height: 256,
+            world_grid_width: 128,
+            world_grid_height: 128,
+            json_version: LDTK_JSON_VERSION.to_owned(),
+            layer_seed: 4592355,
+        }
+    }
+}
+
+/// How a pyxel layer is converted
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LayerSettings {
+    /// Identifier of the LDtk layer, defaults to the pyxel layer name
+    #[serde(default)]
+    pub rename: Option<String>,
+    /// Leave the layer out of the conversion
+    #[serde(default)]
+    pub skip: bool,
+    /// Type of the LDtk layer (default: Tiles). AutoLayer layers use the tileset of the tiles
+    /// as auto-layer tileset, IntGrid and Entities layers are filled from the tiles
+    #[serde(rename = "type", default)]
+    pub layer_type: Option<Type>,
+    /// IntGrid layers: pyxel tile index -> IntGrid value (1 for unlisted tiles)
+    #[serde(default)]
+    pub values: BTreeMap<i64, i64>,
+    /// Entities layers: pyxel tile index -> entity identifier
+    #[serde(default)]
+    pub entities: BTreeMap<i64, String>,
+}
+
+/// Where the outputs are written. Command line options take precedence
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct OutputSettings {
+    /// Output directory, relative to the config file
+    #[serde(rename = "outDir", default)]
+    pub out_dir: Option<String>,
+    #[serde(rename = "ldtkName", default)]
+    pub ldtk_name: Option<String>,
+    #[serde(rename = "tilesetName", default)]
+    pub tileset_name: Option<String>,
+    #[serde(rename = "tilesetRelPath", default)]
+    pub tileset_rel_path: Option<String>,
+}
+
+impl LayerSettings {
+    /// Identifier of the LDtk layer of a pyxel layer
+    pub fn identifier<'a>(&'a self, name: &'a str) -> &'a str {
+        self.rename.as_deref().unwrap_or(name)
+    }
+
+    /// Whether the layer is converted into tiles (Tiles or AutoLayer)
+    pub fn has_tiles(&self) -> bool {
+        matches!(self.layer_type, None | Some(Type::Tiles) | Some(Type::AutoLayer))
+    }
+
+    /// Collision settings converting an IntGrid layer
+    pub fn collision_map(&self, name: &str) -> Option<CollisionMap> {
+        match self.layer_type {
+            Some(Type::IntGrid) => Some(CollisionMap {
+                layer: self.identifier(name).to_owned(),
+                values: vec![],
+                tiles: self.values.to_owned(),
+                source_layer: Some(name.to_owned()),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Entity settings converting an Entities layer
+    pub fn entity_map(&self, name: &str) -> Option<EntityMap> {
+        match self.layer_type {
+            Some(Type::Entities) => Some(EntityMap {
+                layer: self.identifier(name).to_owned(),
+                source_layer: name.to_owned(),
+                tiles: self.entities.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+}
@@ -1,39 +1,34 @@
-use std::path::{Path, PathBuf};
-use std::fs;
-use std::io::{Read, Write};
+use std::io::{Read, Seek};
 use crate::ldtk::*;
-use std::fs::File;
-use std::collections::HashMap;
-use crate::SharedData;
-use serde_json::{Map, Value};
-
-type JsonMap = HashMap<String, serde_json::Value>;
-
-pub struct Coord {
-    pub x: i64,
-    pub y: i64,
-}
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use zip::ZipArchive;
+use crate::animation::{merge_animation_enum, TileAnimation};
+use crate::tileset::{page_name, page_rel_path, AtlasTile, DocTiles, SharedData};
+use crate::error::{ConvertError, Result};
+use crate::pyxel::{Layer, PyxelDoc, TileRef, DOC_DATA_ENTRY};
+use crate::layers::tile_layers;
+use crate::palette::{int_grid_values, palette_colors};
+use crate::uid::UidAllocator;
+use crate::collision::CollisionMap;
+use crate::config::ProjectSettings;
+use crate::entity::EntityMap;
+use crate::ConvertOptions;
+
+/// LDtk file format version written by the converter
+pub const LDTK_JSON_VERSION: &str = "0.8.1";
 
 // ------------------------------------------------------
-pub fn get_pyxel_json_map(path: &Path) -> JsonMap {
+pub fn get_pyxel_doc<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<PyxelDoc> {
     let mut json_docdata = "".to_owned();
-    let mut docdata_filename = "".to_owned();
-
-    let pyxel = fs::File::open(path).unwrap();
-    let mut archive = zip::ZipArchive::new(pyxel).unwrap();
 
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let filename = file.name().clone();
-        if filename.ends_with("json") {
-            docdata_filename.push_str(file.name().clone());
-            file.read_to_string(&mut json_docdata).expect("Read to string error");
-        }
-    }
-    println!("JSON: {:#?}\n", docdata_filename);
+    let mut file = archive
+        .by_name(DOC_DATA_ENTRY)
+        .map_err(|err| ConvertError::zip(DOC_DATA_ENTRY, err))?;
+    file.read_to_string(&mut json_docdata)
+        .map_err(|err| ConvertError::io(DOC_DATA_ENTRY, err))?;
     // println!("JSON data: {:#?}",json_data);
 
-    serde_json::from_str(&json_docdata).expect("JSON not valid")
+    PyxelDoc::from_json(&json_docdata)
 }
 
 fn _json_create_example() {
@@ -47,8 +42,7 @@ fn _json_create_example() {
     "#;
 }
 
-fn build_ldtk_layer_instance(layer_uid: i64) -> LayerInstance {
-    println!(">>> building layer instance...");
+fn build_ldtk_layer_instance(layer_uid: i64, seed: i64) -> LayerInstance {
     LayerInstance {
         c_wid: 0, // map_w,
         c_hei: 0, // map_h,
@@ -59,25 +53,25 @@ fn build_ldtk_layer_instance(layer_uid: i64) -> LayerInstance {
         px_total_offset_y: 0,
         tileset_def_uid: Some(layer_uid),
         tileset_rel_path: Some("".to_owned()),
-        /// Layer type (possible values: IntGrid, Entities, Tiles or AutoLayer)
+        // Layer type (possible values: IntGrid, Entities, Tiles or AutoLayer)
         layer_instance_type: "Tiles".to_owned(),
         auto_layer_tiles: vec![],
         entity_instances: vec![],
         grid_tiles: vec![],
         int_grid: Option::None,
         int_grid_csv: vec![],
-        /// Reference the Layer definition UID (important!)
+        // Reference the Layer definition UID (important!)
         layer_def_uid: layer_uid,
         level_id: 0,
         override_tileset_uid: Option::None,
         px_offset_x: 0,
         px_offset_y: 0,
-        seed: 4592355,
+        seed,
         visible: true,
     }
 }
 
-fn build_ldtk_level(uid: usize, name: &str) -> Level {
+fn build_ldtk_level(uid: i64, name: &str) -> Level {
     Level {
         bg_color: "".to_owned(),
         bg_pos: Option::None,
@@ -93,69 +87,52 @@ fn build_ldtk_level(uid: usize, name: &str) -> Level {
         layer_instances: Option::Some(vec![]),
         px_wid: 0, //canvas_width,
         px_hei: 0, //canvas_height,
-        uid: uid as i64,
+        uid,
         world_x: 0,
         world_y: 0,
     }
 }
 
-fn build_ldtk(tileset: TilesetDefinition, layers: &Map<String, Value>) -> Ldtk {
-    let tile_grid_size = tileset.tile_grid_size;
-
-    let _int_grid_val_def = IntGridValueDefinition {
-        value: 1,
-        identifier: Option::None,
-        color: "#000000".to_string(),
-    };
-
-    let mut layer_definitions: Vec<LayerDefinition> = vec![];
-
-    // iterate pyxel layers to build ldtk layers
-    for (li, layer) in layers.iter().rev().enumerate() {
-        let l = layer.1.as_object().unwrap();
-        let layer_name = l["name"].as_str().unwrap();
-        println!("layer defs > pyxel layer = {}", layer_name);
-
-        layer_definitions.push(LayerDefinition {
-            layer_definition_type: "Tiles".to_string(),
-            identifier: layer_name.to_owned(),
-            //uid: tileset.uid,
-            uid: li as i64,
-            grid_size: tile_grid_size,
-            display_opacity: 1.0,
-            px_offset_x: 0,
-            px_offset_y: 0,
-            required_tags: vec![],
-            excluded_tags: vec![],
-            int_grid_values: vec![],
-            auto_tileset_def_uid: Option::None,
-            auto_rule_groups: vec![],
-            auto_source_layer_def_uid: Option::None,
-            tileset_def_uid: Some(tileset.uid),
-            tile_pivot_x: 0.,
-            tile_pivot_y: 0.,
-            purple_type: Type::Tiles,
-        });
+fn build_ldtk_layer_definition(uid: i64, name: &str, grid_size: i64, tileset_uid: i64, opacity: f64) -> LayerDefinition {
+    LayerDefinition {
+        layer_definition_type: "Tiles".to_string(),
+        identifier: name.to_owned(),
+        uid,
+        grid_size,
+        display_opacity: opacity,
+        px_offset_x: 0,
+        px_offset_y: 0,
+        required_tags: vec![],
+        excluded_tags: vec![],
+        int_grid_values: vec![],
+        auto_tileset_def_uid: Option::None,
+        auto_rule_groups: vec![],
+        auto_source_layer_def_uid: Option::None,
+        tileset_def_uid: Some(tileset_uid),
+        tile_pivot_x: 0.,
+        tile_pivot_y: 0.,
+        purple_type: Type::Tiles,
     }
+}
 
+fn build_ldtk(tile_grid_size: i64, world_layout: WorldLayout, project: &ProjectSettings) -> Ldtk {
     let defs = Definitions {
         entities: vec![],
         enums: vec![],
         external_enums: vec![],
-        //layers: vec![layerDef],
-        layers: layer_definitions,
+        layers: vec![],
         level_fields: vec![],
-        tilesets: vec![tileset],
+        tilesets: vec![],
     };
 
     Ldtk {
-        backup_limit: 3,
-        backup_on_save: true,
-        bg_color: "#000000".to_string(),
+        backup_limit: project.backup_limit,
+        backup_on_save: project.backup_on_save,
+        bg_color: project.bg_color.to_owned(),
         default_grid_size: tile_grid_size,
-        default_level_bg_color: "#333333".to_string(),
-        default_level_height: 256,
-        default_level_width: 256,
+        default_level_bg_color: project.default_level_bg_color.to_owned(),
+        default_level_height: project.default_level_height,
+        default_level_width: project.default_level_width,
         default_pivot_x: 0.,
         default_pivot_y: 0.,
         defs,
@@ -163,153 +140,878 @@ fn build_ldtk(tileset: TilesetDefinition, layers: &Map<String, Value>) -> Ldtk {
         export_tiled: false,
         external_levels: false,
         flags: vec![],
-        json_version: "0.8.1".to_owned(),
+        json_version: project.json_version.to_owned(),
         levels: vec![],
         minify_json: false,
         next_uid: 1,
         png_file_pattern: Option::None,
-        world_grid_width: 128,
-        world_grid_height: 128,
-        world_layout: WorldLayout::Free,
+        world_grid_width: project.world_grid_width,
+        world_grid_height: project.world_grid_height,
+        world_layout,
+    }
+}
+
+/// Add the tileset definition to the project, or replace the one with the same identifier.
+/// Returns the tileset uid.
+fn merge_tileset(ldtk: &mut Ldtk, uids: &mut UidAllocator, mut tileset: TilesetDefinition) -> i64 {
+    let tilesets = &mut ldtk.defs.tilesets;
+    match tilesets.iter_mut().find(|def| def.identifier == tileset.identifier) {
+        Some(existing) => {
+            tileset.uid = existing.uid;
+            *existing = tileset;
+            existing.uid
+        }
+        None => {
+            tileset.uid = uids.alloc();
+            let uid = tileset.uid;
+            tilesets.push(tileset);
+            uid
+        }
+    }
+}
+
+/// Position of a new layer definition, from the identifiers of the layers around it in its
+/// document (`(above, below)`, only the ones already defined count): right below the `above`
+/// one, else right above the `below` one, else on top of the other layers
+fn new_layer_position(layers: &[LayerDefinition], (above, below): (Option<&str>, Option<&str>)) -> usize {
+    let position = |name: &str| layers.iter().position(|def| def.identifier == name);
+    match (above.and_then(position), below.and_then(position)) {
+        (Some(pos), _) => pos + 1,
+        (None, Some(pos)) => pos,
+        (None, None) => 0,
+    }
+}
+
+/// Add a Tiles layer definition to the project, or update the one with the same identifier
+/// (its type, grid, tileset and opacity: the settings made in LDtk are kept).
+/// A new definition goes between its `neighbours` (see `new_layer_position`).
+/// Returns the layer definition uid.
+fn merge_layer_definition(
+    ldtk: &mut Ldtk,
+    uids: &mut UidAllocator,
+    name: &str,
+    grid_size: i64,
+    tileset_uid: i64,
+    opacity: f64,
+    neighbours: (Option<&str>, Option<&str>),
+) -> i64 {
+    let layers = &mut ldtk.defs.layers;
+    match layers.iter_mut().find(|def| def.identifier == name) {
+        Some(existing) => {
+            existing.identifier = name.to_owned();
+            existing.layer_definition_type = "Tiles".to_owned();
+            existing.purple_type = Type::Tiles;
+            existing.grid_size = grid_size;
+            existing.tileset_def_uid = Some(tileset_uid);
+            existing.auto_tileset_def_uid = Option::None;
+            existing.display_opacity = opacity;
+            existing.uid
+        }
+        None => {
+            let uid = uids.alloc();
+            let pos = new_layer_position(layers, neighbours);
+            layers.insert(pos, build_ldtk_layer_definition(uid, name, grid_size, tileset_uid, opacity));
+            uid
+        }
+    }
+}
+
+/// Add a layer definition without tileset (IntGrid or Entities) to the project, or update the
+/// type and grid size of the one with the same identifier.
+/// A new definition goes between its `neighbours` (see `new_layer_position`).
+/// Returns the layer definition.
+fn merge_tileless_definition<'a>(
+    ldtk: &'a mut Ldtk,
+    uids: &mut UidAllocator,
+    name: &str,
+    grid_size: i64,
+    layer_type: Type,
+    neighbours: (Option<&str>, Option<&str>),
+) -> &'a mut LayerDefinition {
+    let layers = &mut ldtk.defs.layers;
+    let pos = match layers.iter().position(|def| def.identifier == name) {
+        Some(pos) => pos,
+        None => {
+            let pos = new_layer_position(layers, neighbours);
+            layers.insert(pos, build_ldtk_layer_definition(uids.alloc(), name, grid_size, 0, 1.0));
+            pos
+        }
+    };
+    let layer_def = &mut layers[pos];
+    layer_def.layer_definition_type = match layer_type {
+        Type::AutoLayer => "AutoLayer",
+        Type::Entities => "Entities",
+        Type::IntGrid => "IntGrid",
+        Type::Tiles => "Tiles",
+    }
+    .to_owned();
+    layer_def.purple_type = layer_type;
+    layer_def.tileset_def_uid = Option::None;
+    layer_def.grid_size = grid_size;
+    layer_def
+}
+
+/// Add an IntGrid layer definition to the project, or update the values of the one with the
+/// same identifier (see `merge_tileless_definition`). A new definition goes on top of the other layers.
+/// Returns the layer definition uid.
+fn merge_int_grid_definition(
+    ldtk: &mut Ldtk,
+    uids: &mut UidAllocator,
+    name: &str,
+    grid_size: i64,
+    values: Vec<IntGridValueDefinition>,
+) -> i64 {
+    let layer_def = merge_tileless_definition(ldtk, uids, name, grid_size, Type::IntGrid, (None, None));
+    layer_def.int_grid_values = values;
+    layer_def.uid
+}
+
+/// Add an entity definition drawn with a tile to the project. The one with the same identifier
+/// gets the new tile and size, its other settings (fields, pivot, color...) are kept.
+/// `tile` is (tileset uid, tile id).
+fn merge_entity_definition(
+    ldtk: &mut Ldtk,
+    uids: &mut UidAllocator,
+    identifier: &str,
+    size: i64,
+    tile: Option<(i64, i64)>,
+) {
+    let entities = &mut ldtk.defs.entities;
+    let entity_def = match entities.iter_mut().find(|def| def.identifier == identifier) {
+        Some(existing) => existing,
+        None => {
+            entities.push(EntityDefinition {
+                color: "#94D9B3".to_owned(),
+                field_defs: vec![],
+                fill_opacity: 1.0,
+                height: size,
+                hollow: false,
+                identifier: identifier.to_owned(),
+                keep_aspect_ratio: false,
+                limit_behavior: LimitBehavior::MoveLastOne,
+                limit_scope: LimitScope::PerLevel,
+                line_opacity: 1.0,
+                max_count: 0,
+                pivot_x: 0.,
+                pivot_y: 0.,
+                render_mode: RenderMode::Tile,
+                resizable_x: false,
+                resizable_y: false,
+                show_name: true,
+                tags: vec![],
+                tile_id: Option::None,
+                tile_render_mode: TileRenderMode::FitInside,
+                tileset_id: Option::None,
+                uid: uids.alloc(),
+                width: size,
+            });
+            entities.last_mut().unwrap()
+        }
+    };
+    entity_def.width = size;
+    entity_def.height = size;
+    entity_def.render_mode = RenderMode::Tile;
+    entity_def.tileset_id = tile.map(|(tileset_uid, _)| tileset_uid);
+    entity_def.tile_id = tile.map(|(_, tile_id)| tile_id);
+}
+
+/// Whether a pyxel layer is left out of the conversion
+fn skip_layer(layer: &Layer, options: &ConvertOptions) -> bool {
+    let collision_layer = options.collision.as_ref().is_some_and(|collision| collision.is_source_layer(layer));
+    let entity_layer = options.entities.as_ref().is_some_and(|entities| entities.is_source_layer(layer));
+    let settings = options.layers.get(&layer.name);
+    let skipped = settings.is_some_and(|settings| settings.skip || !settings.has_tiles());
+    collision_layer || entity_layer || skipped || options.skip_hidden_layers && (layer.hidden || layer.muted)
+}
+
+/// Identifier of the LDtk layer of a pyxel layer
+fn layer_identifier<'a>(layer: &'a Layer, options: &'a ConvertOptions) -> &'a str {
+    match options.layers.get(&layer.name) {
+        Some(settings) => settings.identifier(&layer.name),
+        None => &layer.name,
+    }
+}
+
+/// Whether a pyxel layer is converted into an AutoLayer layer instead of a Tiles layer
+fn is_auto_layer(layer: &Layer, options: &ConvertOptions) -> bool {
+    options.layers.get(&layer.name).is_some_and(|settings| matches!(settings.layer_type, Some(Type::AutoLayer)))
+}
+
+/// Type of the layer filled from the tiles of a pyxel layer (IntGrid or Entities type in the
+/// layer settings), None for a pyxel layer converted into tiles or skipped
+fn filled_layer_type(layer: &Layer, options: &ConvertOptions) -> Option<Type> {
+    match options.layers.get(&layer.name) {
+        Some(settings) if !settings.skip && !settings.has_tiles() => settings.layer_type.clone(),
+        _ => None,
+    }
+}
+
+/// Turn a Tiles layer definition into an AutoLayer one using the same tileset
+/// (a new definition has no rules, the rules of an existing one are kept)
+fn auto_layer_definition(layer_def: &mut LayerDefinition) {
+    layer_def.layer_definition_type = "AutoLayer".to_owned();
+    layer_def.purple_type = Type::AutoLayer;
+    layer_def.auto_tileset_def_uid = layer_def.tileset_def_uid.take();
+}
+
+/// LDtk opacity (0 to 1) of a pyxel layer
+fn layer_opacity(layer: &Layer) -> f64 {
+    f64::from(layer.alpha) / 255.0
+}
+
+/// Empty layer instance of a layer definition
+fn empty_layer_instance(layer_def: &LayerDefinition, level: &Level, seed: i64) -> LayerInstance {
+    let grid_size = layer_def.grid_size.max(1);
+    let mut layer_instance = build_ldtk_layer_instance(layer_def.uid, seed);
+    layer_instance.identifier = layer_def.identifier.to_owned();
+    layer_instance.layer_instance_type = layer_def.layer_definition_type.to_owned();
+    layer_instance.c_wid = (level.px_wid + grid_size - 1) / grid_size;
+    layer_instance.c_hei = (level.px_hei + grid_size - 1) / grid_size;
+    layer_instance.grid_size = grid_size;
+    layer_instance.level_id = level.uid;
+    layer_instance.tileset_def_uid = layer_def.tileset_def_uid.or(layer_def.auto_tileset_def_uid);
+    layer_instance.tileset_rel_path = Option::None;
+    layer_instance.opacity = layer_def.display_opacity;
+    if let Type::IntGrid = layer_def.purple_type {
+        layer_instance.int_grid_csv = vec![0; (layer_instance.c_wid * layer_instance.c_hei) as usize];
+    }
+    layer_instance
+}
+
+/// Sort the layer instances of a level in layer definition order,
+/// adding empty instances for the definitions the level doesn't have yet
+/// (or whose type changed)
+fn sync_layer_instances(
+    defs: &[LayerDefinition],
+    level: &mut Level,
+    mut layer_instances: Vec<LayerInstance>,
+    seed: i64,
+) {
+    let mut synced = vec![];
+    for layer_def in defs.iter() {
+        let same_type = |instance: &LayerInstance| instance.layer_instance_type == layer_def.layer_definition_type;
+        match layer_instances.iter().position(|instance| instance.layer_def_uid == layer_def.uid) {
+            Some(pos) if same_type(&layer_instances[pos]) => synced.push(layer_instances.remove(pos)),
+            _ => synced.push(empty_layer_instance(layer_def, level, seed)),
+        }
+    }
+    level.layer_instances = Some(synced);
+}
+
+/// Place the new levels in the world according to the world layout,
+/// after the levels already in the project
+fn arrange_levels(ldtk: &mut Ldtk, new_levels: &[i64]) {
+    let grid_w = ldtk.world_grid_width.max(1);
+    let grid_h = ldtk.world_grid_height.max(1);
+    let world_layout = ldtk.world_layout;
+    let (existing, mut levels): (Vec<&mut Level>, Vec<&mut Level>) =
+        ldtk.levels.iter_mut().partition(|level| !new_levels.contains(&level.uid));
+    let right = existing.iter().map(|level| level.world_x + level.px_wid).max().unwrap_or(0);
+    let bottom = existing.iter().map(|level| level.world_y + level.px_hei).max().unwrap_or(0);
+
+    match world_layout {
+        WorldLayout::LinearVertical => {
+            let mut world_y = bottom;
+            for level in levels.iter_mut() {
+                level.world_x = 0;
+                level.world_y = world_y;
+                world_y += level.px_hei;
+            }
+        }
+        WorldLayout::GridVania => {
+            // levels in a square-ish grid, each cell snapped to the world grid
+            let columns = (levels.len() as f64).sqrt().ceil().max(1.) as usize;
+            let max_w = levels.iter().map(|level| level.px_wid).max().unwrap_or(0);
+            let max_h = levels.iter().map(|level| level.px_hei).max().unwrap_or(0);
+            let cell_w = ((max_w + grid_w - 1) / grid_w).max(1) * grid_w;
+            let cell_h = ((max_h + grid_h - 1) / grid_h).max(1) * grid_h;
+            let origin_x = (right + grid_w - 1) / grid_w * grid_w;
+            for (li, level) in levels.iter_mut().enumerate() {
+                level.world_x = origin_x + (li % columns) as i64 * cell_w;
+                level.world_y = (li / columns) as i64 * cell_h;
+            }
+        }
+        // Free and LinearHorizontal: levels side by side
+        _ => {
+            let mut world_x = right;
+            for level in levels.iter_mut() {
+                level.world_x = world_x;
+                level.world_y = 0;
+                world_x += level.px_wid;
+            }
+        }
+    }
+}
+
+// -----------------------------------------------------
+/// Indices of the pyxel tiles used with a 90° or 270° rotation in any layer
+pub fn rotated_tile_indices(doc: &PyxelDoc) -> BTreeSet<i64> {
+    doc.canvas
+        .layers
+        .values()
+        .flat_map(|layer| layer.tile_refs.values())
+        .filter(|tile_ref| tile_ref.rot % 2 == 1)
+        .map(|tile_ref| tile_ref.index)
+        .collect()
+}
+
+/// Map a pyxel tile transformation to LDtk.
+/// Pyxel Edit mirrors the tile first (flipX), then rotates it clockwise (rot * 90°).
+/// LDtk only knows flips, so 90° rotations use the rotated copy of the tile
+/// (see `DocTiles::rotated_tiles`) followed by flips.
+/// Returns (use rotated copy, LDtk flip bits)
+fn tile_ref_transform(tile_ref: &TileRef) -> (bool, i64) {
+    const FLIP_X: i64 = 1;
+    const FLIP_Y: i64 = 2;
+    match (tile_ref.flip_x, tile_ref.rot % 4) {
+        (false, 0) => (false, 0),
+        (false, 1) => (true, 0),
+        (false, 2) => (false, FLIP_X | FLIP_Y),
+        (false, _) => (true, FLIP_X | FLIP_Y),
+        (true, 0) => (false, FLIP_X),
+        (true, 1) => (true, FLIP_Y),
+        (true, 2) => (false, FLIP_Y),
+        (true, _) => (true, FLIP_X),
+    }
+}
+
+// -----------------------------------------------------
+/// Tile of the tileset image drawing a pyxel tile reference
+fn tile_ref_atlas_tile(doc_tiles: &DocTiles, tile_ref: &TileRef) -> Result<AtlasTile> {
+    let (rotated, _) = tile_ref_transform(tile_ref);
+    match doc_tiles.rotated_tiles.get(&tile_ref.index) {
+        Some(&rotated_tile) if rotated => Ok(rotated_tile),
+        _ => match doc_tiles.tile_ids.get(&tile_ref.index) {
+            Some(&tile) => Ok(tile),
+            None => Err(ConvertError::MissingEntry { entry: format!("tile{}.png", tile_ref.index) }),
+        },
+    }
+}
+
+/// Tile id in the tileset image and LDtk flip bits drawing a pyxel tile reference
+fn tile_ref_ldtk_tile(doc_tiles: &DocTiles, tile_ref: &TileRef) -> Result<(i64, i64)> {
+    let (_, flip_bits) = tile_ref_transform(tile_ref);
+    let atlas_tile = tile_ref_atlas_tile(doc_tiles, tile_ref)?;
+    // flips commute: the stored tile flips are combined with the tile reference ones
+    Ok((atlas_tile.tile_id, flip_bits ^ atlas_tile.flip))
+}
+
+/// LDtk layers of a pyxel layer: one for the first tileset image page, and one per other page
+/// used by its tiles. They are named like the pages (see `page_name`), so a layer keeps its
+/// identifier from one conversion to the next one: the first one is always there, with the
+/// layer name, even when it has no tile.
+fn layer_pages(name: &str, pages: Option<&BTreeSet<usize>>) -> Vec<(usize, String)> {
+    let mut pages: BTreeSet<usize> = pages.cloned().unwrap_or_default();
+    pages.insert(0);
+    pages.into_iter().map(|page| (page, page_name(name, page))).collect()
+}
+
+/// Page of a page identifier (`name` or `name_N`, see `page_name`)
+fn page_of_name(identifier: &str, name: &str) -> Option<usize> {
+    if identifier == name {
+        return Some(0);
+    }
+    let number: usize = identifier.strip_prefix(name)?.strip_prefix('_')?.parse().ok()?;
+    match number {
+        0 | 1 => None,
+        _ => page_name(name, number - 1).eq(identifier).then(|| number - 1),
+    }
+}
+
+/// Remove the tileset pages and the page layers of a previous conversion which this conversion
+/// doesn't produce anymore (the tileset was split into more images). Their level instances go
+/// with them (see `sync_layer_instances`), other references to the removed tilesets are cleared.
+fn remove_stale_pages(
+    ldtk: &mut Ldtk,
+    data: &SharedData,
+    tileset_rel_path: &str,
+    layer_pages: &HashMap<&str, Vec<(usize, String)>>,
+) {
+    let page_count = data.pages.len();
+    let stale_tileset = |def: &TilesetDefinition| match page_of_name(&def.identifier, &data.tileset_filename) {
+        Some(page) => page >= page_count && def.rel_path == page_rel_path(tileset_rel_path, page),
+        None => false,
+    };
+    let stale_tilesets: Vec<i64> = ldtk.defs.tilesets.iter().filter(|def| stale_tileset(def)).map(|def| def.uid).collect();
+    ldtk.defs.tilesets.retain(|def| !stale_tilesets.contains(&def.uid));
+
+    // page layers of the converted layers, using a page of the tileset
+    let page_tilesets: Vec<i64> = ldtk
+        .defs
+        .tilesets
+        .iter()
+        .filter(|def| page_of_name(&def.identifier, &data.tileset_filename).is_some())
+        .map(|def| def.uid)
+        .chain(stale_tilesets.iter().cloned())
+        .collect();
+    ldtk.defs.layers.retain(|def| {
+        let tileset_uid = def.tileset_def_uid.or(def.auto_tileset_def_uid);
+        let stale_page = |(name, pages): (&&str, &Vec<(usize, String)>)| {
+            let page = page_of_name(&def.identifier, name);
+            page.is_some_and(|page| page > 0 && !pages.iter().any(|(produced, _)| *produced == page))
+        };
+        !(tileset_uid.is_some_and(|uid| page_tilesets.contains(&uid)) && layer_pages.iter().any(stale_page))
+    });
+
+    let stale = |uid: &Option<i64>| uid.is_some_and(|uid| stale_tilesets.contains(&uid));
+    for layer_def in ldtk.defs.layers.iter_mut() {
+        if stale(&layer_def.tileset_def_uid) {
+            layer_def.tileset_def_uid = Option::None;
+        }
+        if stale(&layer_def.auto_tileset_def_uid) {
+            layer_def.auto_tileset_def_uid = Option::None;
+        }
+    }
+    for entity_def in ldtk.defs.entities.iter_mut().filter(|def| stale(&def.tileset_id)) {
+        entity_def.tileset_id = Option::None;
+        entity_def.tile_id = Option::None;
+    }
+    for enum_def in ldtk.defs.enums.iter_mut().filter(|def| stale(&def.icon_tileset_uid)) {
+        enum_def.icon_tileset_uid = Option::None;
+        enum_def.values.iter_mut().for_each(|value| value.tile_id = Option::None);
     }
 }
 
 // -----------------------------------------------------
+/// LDtk tiles of a pyxel layer, grouped by tileset image page
 fn pyxel_tilerefs_to_ldtk(
-    tile_w: i64,
-    tilerefs: &Map<String, Value>,
+    data: &SharedData,
+    doc_tiles: &DocTiles,
+    tilerefs: &BTreeMap<i64, TileRef>,
     map_w: i64,
     _map_h: i64,
-) -> Vec<TileInstance> {
-    let mut grid_tiles: Vec<TileInstance> = vec![];
+) -> Result<BTreeMap<usize, Vec<TileInstance>>> {
+    let tile_w = data.tile_w;
+    let tile_h = data.tile_h;
+    let mut grid_tiles: BTreeMap<usize, Vec<TileInstance>> = BTreeMap::new();
 
     // iterate Pyxel Edit tile references
-    for (key, value) in tilerefs {
-        let tile_ref = value.as_object().unwrap();
-        let tile_pos = key.parse::<i64>().unwrap();
-        let tile_index = tile_ref["index"].as_i64().unwrap();
+    for (&tile_pos, tile_ref) in tilerefs {
+        let (tile_index, flip_bits) = tile_ref_ldtk_tile(doc_tiles, tile_ref)?;
         //print!("pos={} index={}", tile_pos, tile_index);
 
         // x,y coords based on pyxel tile position
-        let pos_x: i64 = (tile_pos % map_w) as i64;
-        let pos_y = (tile_pos / map_w) as i64;
+        let pos_x = tile_pos % map_w;
+        let pos_y = tile_pos / map_w;
         //print!("x={} y={} ",pos_x,pos_y);
-        grid_tiles.push(TileInstance {
-            /// Pixel coordinates of the tile in the **layer**
-            /// (`[x,y]` format). Don't forget optional
-            /// layer offsets, if they exist!
-            px: vec![(pos_x * tile_w) as i64, (pos_y * tile_w) as i64],
+        grid_tiles.entry(data.page_of(tile_index)).or_default().push(TileInstance {
+            // Pixel coordinates of the tile in the **layer**
+            // (`[x,y]` format). Don't forget optional
+            // layer offsets, if they exist!
+            px: vec![pos_x * tile_w, pos_y * tile_h],
             //px: vec![0, 0],
-            /// Pixel coordinates of the tile in the **tileset** (`[x,y]` format)
-            src: vec![0, 0],
-            /// flip bits
-            f: 0,
-            /// tile id in the corresponding tileset
-            t: tile_index,
-            /// Internal data used by the editor.
-            /// For auto-layer tiles: `[ruleId, coordId]`
-            /// For tile-layer tiles: `[coordId]`
+            // Pixel coordinates of the tile in the **tileset** (`[x,y]` format)
+            src: data.tile_src(tile_index),
+            // flip bits
+            f: flip_bits,
+            // tile id in the corresponding tileset
+            t: data.page_tile_id(tile_index),
+            // Internal data used by the editor.
+            // For auto-layer tiles: `[ruleId, coordId]`
+            // For tile-layer tiles: `[coordId]`
             // d: vec![tile_index],
             d: vec![pos_y * map_w + pos_x],
         });
     }
 
-    grid_tiles
+    Ok(grid_tiles)
 }
 
 // -----------------------------------------------------
 // Conversion from Pyxel Edit (Json) to LDtk
 // -----------------------------------------------------
-pub fn convert(path: &Path, data: &SharedData) {
-    println!("------------ CONVERT -------------");
-    let layer_uid = 1;
-    let mut tileset_filename: String = data.tileset_filename.to_owned();
-    tileset_filename.push_str(".png");
-    println!("tileset_filename: {}", tileset_filename);
-
-    let json = get_pyxel_json_map(path);
-
-    let ver = json["version"].as_str().unwrap();
-    let pyxel_name = json["name"].as_str().unwrap();
-    println!("--- Pyxel Edit: filename '{}' (ver {}) ---", pyxel_name, ver);
-
-    // -- get info from pyxel edit file
-    let canvas = json["canvas"].as_object().unwrap();
-    let canvas_width = canvas["width"].as_i64().unwrap();
-    let canvas_height = canvas["height"].as_i64().unwrap();
-    let tile_w = canvas["tileWidth"].as_i64().unwrap();
-    let tile_h = canvas["tileHeight"].as_i64().unwrap();
-    let map_w = canvas_width / tile_w;
-    let map_h = canvas_height / tile_h;
-    //println!("canvas w={} h={}", canvas_width, canvas_height);
-    //println!("map w={} h={}", map_w, map_h);
-
-    // LDtk tileset definition
-    let tileset = TilesetDefinition {
-        identifier: data.tileset_filename.to_owned(),
-        uid: layer_uid,
-        rel_path: String::from(tileset_filename.to_owned()),
-        px_wid: data.tileset_w,
-        px_hei: data.tileset_h,
-        tile_grid_size: data.tile_w,
-        spacing: 0,
-        padding: 0,
-        saved_selections: vec![],
-        cached_pixel_data: Option::None,
+/// Convert the pyxel documents into a new LDtk project, or into `base`: the tileset,
+/// the layer definitions and the levels with the same identifiers are replaced,
+/// everything else in `base` is kept.
+pub fn convert(
+    docs: &[&PyxelDoc],
+    data: &SharedData,
+    tileset_rel_path: &str,
+    options: &ConvertOptions,
+    base: Option<Ldtk>,
+    animations: &[TileAnimation],
+    warnings: &mut Vec<String>,
+) -> Result<Ldtk> {
+    // LDtk layers and tilesets only have a single grid size
+    let tile_w = data.tile_w;
+    let tile_h = data.tile_h;
+    if tile_w != tile_h {
+        return Err(ConvertError::unsupported(format!(
+            "non-square tiles ({}x{}) cannot be represented in LDtk {} (single grid size)",
+            tile_w, tile_h, LDTK_JSON_VERSION
+        )));
+    }
+
+    let mut ldtk: Ldtk = match base {
+        Some(base) if base.external_levels => {
+            return Err(ConvertError::unsupported("merging into a project with separate level files"));
+        }
+        Some(base) => base,
+        None => build_ldtk(tile_w, options.world_layout, &options.project),
     };
+    let mut uids = UidAllocator::new(ldtk.next_uid);
+
+    // LDtk tileset definitions, one per tileset image page
+    let mut tileset_uids: Vec<i64> = vec![];
+    for (page, &(page_w, page_h)) in data.pages.iter().enumerate() {
+        let tileset = TilesetDefinition {
+            identifier: page_name(&data.tileset_filename, page),
+            uid: 0,
+            rel_path: page_rel_path(tileset_rel_path, page),
+            px_wid: page_w,
+            px_hei: page_h,
+            tile_grid_size: data.tile_w,
+            spacing: data.spacing,
+            padding: data.padding,
+            saved_selections: vec![],
+            cached_pixel_data: Option::None,
+        };
+        tileset_uids.push(merge_tileset(&mut ldtk, &mut uids, tileset));
+    }
+
+    // tile layers of each document, groups resolved
+    let doc_layers: Vec<Vec<Layer>> = docs.iter().map(|doc| tile_layers(doc, options, warnings)).collect();
+    // the layer settings use the names of the layers with the groups resolved
+    for name in options.layers.keys() {
+        if !doc_layers.iter().flatten().any(|layer| &layer.name == name) {
+            warnings.push(format!("no layer named '{}' for the layer settings", name));
+        }
+    }
+
+    // tileset image pages used by each LDtk layer
+    let mut used_pages: HashMap<&str, BTreeSet<usize>> = HashMap::new();
+    for (layers, doc_tiles) in doc_layers.iter().zip(data.docs.iter()) {
+        for layer in layers.iter() {
+            let pages = used_pages.entry(layer_identifier(layer, options)).or_default();
+            for tile_ref in layer.tile_refs.values() {
+                if let Ok(tile) = tile_ref_atlas_tile(doc_tiles, tile_ref) {
+                    pages.insert(data.page_of(tile.tile_id));
+                }
+            }
+        }
+    }
+    // LDtk layers of each converted pyxel layer identifier, one per tileset image page
+    let page_layers: HashMap<&str, Vec<(usize, String)>> = doc_layers
+        .iter()
+        .flatten()
+        .filter(|layer| !skip_layer(layer, options))
+        .map(|layer| layer_identifier(layer, options))
+        .map(|identifier| (identifier, layer_pages(identifier, used_pages.get(identifier))))
+        .collect();
+    remove_stale_pages(&mut ldtk, data, tileset_rel_path, &page_layers);
+
+    // layer definitions are shared by all levels: one per distinct LDtk layer identifier
+    // (and per tileset image page when the tileset is split), top-most layer first
+    // (a new layer goes between its neighbours in the document, so another document or the
+    // merged project can't push it to the bottom). The pyxel layers with the IntGrid or Entities
+    // type get their definition here too, without page, to keep their place
+    for layers in doc_layers.iter() {
+        let doc_defs: Vec<(&Layer, Option<usize>, String)> = layers
+            .iter()
+            .flat_map(|layer| {
+                let identifier = layer_identifier(layer, options);
+                let pages: Vec<(Option<usize>, String)> = if !skip_layer(layer, options) {
+                    page_layers[identifier].iter().map(|(page, name)| (Some(*page), name.to_owned())).collect()
+                } else if filled_layer_type(layer, options).is_some() {
+                    vec![(None, identifier.to_owned())]
+                } else {
+                    vec![]
+                };
+                pages.into_iter().map(move |(page, name)| (layer, page, name))
+            })
+            .collect();
+        for (pos, (layer, page, name)) in doc_defs.iter().enumerate() {
+            let above = pos.checked_sub(1).map(|above| doc_defs[above].2.as_str());
+            let below = doc_defs[pos + 1..]
+                .iter()
+                .map(|(_, _, below)| below.as_str())
+                .find(|below| ldtk.defs.layers.iter().any(|def| def.identifier == *below));
+            match (page, filled_layer_type(layer, options)) {
+                (Some(page), _) => {
+                    let (tileset_uid, opacity) = (tileset_uids[*page], layer_opacity(layer));
+                    let uid =
+                        merge_layer_definition(&mut ldtk, &mut uids, name, tile_w, tileset_uid, opacity, (above, below));
+                    if is_auto_layer(layer, options) {
+                        ldtk.defs.layers.iter_mut().filter(|def| def.uid == uid).for_each(auto_layer_definition);
+                    }
+                }
+                (None, Some(layer_type)) => {
+                    merge_tileless_definition(&mut ldtk, &mut uids, name, tile_w, layer_type, (above, below));
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    // layers filled from the tiles: the collision and entity settings, and the pyxel layers
+    // with the IntGrid or Entities type
+    let configured = options.layers.iter().filter(|(_, settings)| !settings.skip);
+    let int_grid_maps: Vec<CollisionMap> = options
+        .collision
+        .iter()
+        .cloned()
+        .chain(configured.clone().filter_map(|(name, settings)| settings.collision_map(name)))
+        .collect();
+    let entity_maps: Vec<EntityMap> = options
+        .entities
+        .iter()
+        .cloned()
+        .chain(configured.filter_map(|(name, settings)| settings.entity_map(name)))
+        .collect();
+
+    // IntGrid layer using the palette colors
+    let colors = docs.iter().find_map(|doc| doc.palette.as_ref()).map(palette_colors).unwrap_or_default();
+    if let Some(name) = &options.palette_int_grid {
+        if colors.is_empty() {
+            warnings.push(format!("no palette color for the '{}' IntGrid layer", name));
+        }
+        merge_int_grid_definition(&mut ldtk, &mut uids, name, tile_w, int_grid_values(&colors));
+    }
+
+    // IntGrid collision layers
+    for collision in int_grid_maps.iter() {
+        merge_int_grid_definition(&mut ldtk, &mut uids, &collision.layer, tile_w, collision.int_grid_values(&colors));
+    }
 
-    let num_layers = canvas.get("numLayers").unwrap();
-    let layers = canvas["layers"].as_object().unwrap();
-    println!("num layers = {}", num_layers);
-
-    let mut ldtk: Ldtk = build_ldtk(tileset, layers);
-
-    for (li, layer) in layers.iter().enumerate() {
-        let l = layer.1.as_object().unwrap();
-        let _layer_type = l["type"].as_str().unwrap();
-        let layer_name = l["name"].as_str().unwrap();
-        //println!("#{}: name='{}' type={}", layer.0, layer_name, layer_type);
-
-        let tile_refs = l["tileRefs"].as_object().unwrap();
-        //println!("num tile refs {}", tile_refs.len());
-        let grid_tiles = pyxel_tilerefs_to_ldtk(tile_w, tile_refs, map_w, map_h);
-
-        let mut layer_instance = build_ldtk_layer_instance(layer_uid);
-        layer_instance.c_wid = map_w;
-        layer_instance.c_hei = map_h;
-        layer_instance.grid_size = tile_w;
-        layer_instance.grid_tiles = grid_tiles;
-        layer_instance.tileset_rel_path = Some(tileset_filename.to_owned());
-        layer_instance.tileset_def_uid = Some(li as i64);
-
-        println!(">>> building level...");
-        let mut level = build_ldtk_level(li, layer_name);
-        level.layer_instances = Some(vec![layer_instance]);
+    // Entities layers, and one entity definition per identifier drawn with its first tile
+    for entities in entity_maps.iter() {
+        merge_tileless_definition(&mut ldtk, &mut uids, &entities.layer, tile_w, Type::Entities, (None, None));
+        for (identifier, index) in entities.entity_tiles() {
+            let tile = data.docs.iter().find_map(|doc_tiles| doc_tiles.tile_ids.get(&index)).map(|tile| {
+                (tileset_uids[data.page_of(tile.tile_id)], data.page_tile_id(tile.tile_id))
+            });
+            if tile.is_none() {
+                warnings.push(format!("entity '{}' has no tile (tile{}.png is missing)", identifier, index));
+            }
+            merge_entity_definition(&mut ldtk, &mut uids, identifier, tile_w, tile);
+        }
+    }
+
+    // one level per pyxel document
+    let mut converted_levels: Vec<i64> = vec![];
+    let mut new_levels: Vec<i64> = vec![];
+    for ((doc, doc_tiles), layers) in docs.iter().zip(data.docs.iter()).zip(doc_layers.iter()) {
+        // -- get info from pyxel edit file
+        let canvas = &doc.canvas;
+        let canvas_width = canvas.width;
+        let canvas_height = canvas.height;
+        let map_w = canvas_width / tile_w;
+        let map_h = canvas_height / tile_h;
+        //println!("canvas w={} h={}", canvas_width, canvas_height);
+        //println!("map w={} h={}", map_w, map_h);
+
+        // replace the level with the same identifier, unless it was converted in this run
+        let mut identifier = doc.name.to_owned();
+        let mut suffix = 1;
+        let is_converted = |ldtk: &Ldtk, identifier: &str| {
+            ldtk.levels.iter().any(|level| level.identifier == identifier && converted_levels.contains(&level.uid))
+        };
+        while is_converted(&ldtk, &identifier) {
+            suffix += 1;
+            identifier = format!("{}_{}", doc.name, suffix);
+        }
+        let existing_pos = ldtk.levels.iter().position(|level| level.identifier == identifier);
+        let (mut level, old_instances) = match existing_pos {
+            Some(pos) => {
+                let mut level = ldtk.levels.remove(pos);
+                let old_instances = level.layer_instances.take().unwrap_or_default();
+                (level, old_instances)
+            }
+            None => {
+                let level = build_ldtk_level(uids.alloc(), &identifier);
+                new_levels.push(level.uid);
+                (level, vec![])
+            }
+        };
         level.px_wid = canvas_width;
         level.px_hei = canvas_height;
-        println!("\tlevel identifier={}", level.identifier);
 
-        ldtk.levels.push(level);
-        println!("------------------");
-    } // -end-layer-
+        // one layer instance per pyxel layer, other layers of the level are kept
+        let mut layer_instances: Vec<LayerInstance> = old_instances;
+        for layer in layers.iter().filter(|layer| !skip_layer(layer, options)) {
+            //println!("name='{}' type={}", layer.name, layer.layer_type);
+            if layer.blend_mode != "normal" {
+                warnings.push(format!(
+                    "'{}': layer '{}' uses the '{}' blend mode, LDtk draws it as normal",
+                    doc.name, layer.name, layer.blend_mode
+                ));
+            }
+            let mut page_tiles = pyxel_tilerefs_to_ldtk(data, doc_tiles, &layer.tile_refs, map_w, map_h)?;
+            let identifier = layer_identifier(layer, options);
+            for &(page, ref name) in page_layers[identifier].iter() {
+                let layer_def = match ldtk.defs.layers.iter().find(|def| def.identifier == *name) {
+                    Some(layer_def) => layer_def,
+                    None => continue,
+                };
+
+                let mut layer_instance = build_ldtk_layer_instance(layer_def.uid, options.project.layer_seed);
+                layer_instance.identifier = layer_def.identifier.to_owned();
+                layer_instance.c_wid = map_w;
+                layer_instance.c_hei = map_h;
+                layer_instance.grid_size = tile_w;
+                let grid_tiles = page_tiles.remove(&page).unwrap_or_default();
+                match layer_def.purple_type {
+                    // auto-layer tiles are `[ruleId, coordId]`, there is no rule
+                    Type::AutoLayer => {
+                        layer_instance.layer_instance_type = "AutoLayer".to_owned();
+                        layer_instance.auto_layer_tiles = grid_tiles
+                            .into_iter()
+                            .map(|tile| TileInstance { d: vec![0, tile.d[0]], ..tile })
+                            .collect();
+                    }
+                    _ => layer_instance.grid_tiles = grid_tiles,
+                }
+                layer_instance.level_id = level.uid;
+                layer_instance.tileset_rel_path = Some(page_rel_path(tileset_rel_path, page));
+                layer_instance.tileset_def_uid = layer_def.tileset_def_uid.or(layer_def.auto_tileset_def_uid);
+                layer_instance.opacity = layer_opacity(layer);
+                layer_instance.visible = !layer.hidden;
+
+                layer_instances.retain(|instance| instance.layer_def_uid != layer_def.uid);
+                layer_instances.push(layer_instance);
+            }
+        } // -end-layer-
+
+        // collisions from the tiles
+        for collision in int_grid_maps.iter() {
+            if let Some(layer_def) = ldtk.defs.layers.iter().find(|def| def.identifier == collision.layer) {
+                let tile_layers: Vec<&Layer> = layers.iter().filter(|layer| !skip_layer(layer, options)).collect();
+                let mut layer_instance = empty_layer_instance(layer_def, &level, options.project.layer_seed);
+                layer_instance.int_grid_csv = collision.int_grid_csv(layers, &tile_layers, map_w, map_h);
+                layer_instance.c_wid = map_w;
+                layer_instance.c_hei = map_h;
+
+                layer_instances.retain(|instance| instance.layer_def_uid != layer_def.uid);
+                layer_instances.push(layer_instance);
+            }
+        }
 
-    let json_save = serde_json::to_string_pretty(&ldtk).unwrap();
+        // entities from the tiles of the entity layers
+        for entities in entity_maps.iter() {
+            if let Some(layer_def) = ldtk.defs.layers.iter().find(|def| def.identifier == entities.layer) {
+                let (entity_refs, unlisted) = entities.entity_refs(layers);
+                if unlisted > 0 {
+                    warnings.push(format!(
+                        "'{}': {} tiles of the '{}' layer are not in the entity mapping",
+                        doc.name, unlisted, entities.source_layer
+                    ));
+                }
+                let mut layer_instance = empty_layer_instance(layer_def, &level, options.project.layer_seed);
+                let mut transformed = 0;
+                for (cell, identifier, tile_ref) in entity_refs.into_iter() {
+                    let entity_def = match ldtk.defs.entities.iter().find(|def| def.identifier == identifier) {
+                        Some(entity_def) => entity_def,
+                        None => continue,
+                    };
+                    // LDtk entities can't be flipped or rotated: they use the untransformed tile
+                    let tile = doc_tiles.tile_ids.get(&tile_ref.index);
+                    if tile_ref_transform(tile_ref) != (false, 0) || tile.is_some_and(|tile| tile.flip != 0) {
+                        transformed += 1;
+                    }
+                    let (pos_x, pos_y) = (cell % map_w, cell / map_w);
+                    // the entity box covers the cell, whatever its pivot
+                    let pivot = vec![entity_def.pivot_x, entity_def.pivot_y];
+                    let offset_x = (entity_def.pivot_x * entity_def.width as f64).round() as i64;
+                    let offset_y = (entity_def.pivot_y * entity_def.height as f64).round() as i64;
+                    layer_instance.entity_instances.push(EntityInstance {
+                        grid: vec![pos_x, pos_y],
+                        identifier: identifier.to_owned(),
+                        pivot,
+                        tile: tile.map(|tile| {
+                            let src = data.tile_src(tile.tile_id);
+                            EntityInstanceTile {
+                                src_rect: vec![src[0], src[1], tile_w, tile_h],
+                                tileset_uid: tileset_uids[data.page_of(tile.tile_id)],
+                            }
+                        }),
+                        def_uid: entity_def.uid,
+                        field_instances: vec![],
+                        height: entity_def.height,
+                        px: vec![pos_x * tile_w + offset_x, pos_y * tile_h + offset_y],
+                        width: entity_def.width,
+                    });
+                }
+                if transformed > 0 {
+                    warnings.push(format!(
+                        "'{}': {} entity tiles are flipped or rotated, LDtk draws entities untransformed",
+                        doc.name, transformed
+                    ));
+                }
+
+                layer_instances.retain(|instance| instance.layer_def_uid != layer_def.uid);
+                layer_instances.push(layer_instance);
+            }
+        }
 
-    // [] WRITE LDTK (json) file
-    let mut ldtk_path = PathBuf::new();
-    ldtk_path.push("target");
-    ldtk_path.push(data.tileset_filename.to_owned());
-    ldtk_path.set_extension("ldtk");
-    let display = ldtk_path.display();
+        sync_layer_instances(&ldtk.defs.layers, &mut level, layer_instances, options.project.layer_seed);
+        converted_levels.push(level.uid);
+        match existing_pos {
+            Some(pos) => ldtk.levels.insert(pos, level),
+            None => ldtk.levels.push(level),
+        }
+    } // -end-doc-
 
-    // Open a file in write-only mode, returns `io::Result<File>`
-    let mut file = match File::create(&ldtk_path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why),
-        Ok(file) => file,
-    };
+    // levels which were not converted still need an instance of the new layer definitions
+    for level in ldtk.levels.iter_mut() {
+        if !converted_levels.contains(&level.uid) {
+            let layer_instances = level.layer_instances.take().unwrap_or_default();
+            sync_layer_instances(&ldtk.defs.layers, level, layer_instances, options.project.layer_seed);
+        }
+    }
+
+    // enum of the animations
+    if let (Some(identifier), false) = (&options.animation_enum, animations.is_empty()) {
+        merge_animation_enum(&mut ldtk, &mut uids, identifier, animations, tile_w);
+    }
 
-    match file.write_all(json_save.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        Ok(_) => println!("successfully wrote to {}", display),
+    arrange_levels(&mut ldtk, &new_levels);
+    ldtk.next_uid = uids.next_uid();
+
+    Ok(ldtk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, Rgba, RgbaImage};
+
+    /// LDtk flips of a tile image
+    fn ldtk_flip(tile: &RgbaImage, flip: i64) -> RgbaImage {
+        let tile = if flip & 1 != 0 { imageops::flip_horizontal(tile) } else { tile.clone() };
+        if flip & 2 != 0 {
+            imageops::flip_vertical(&tile)
+        } else {
+            tile
+        }
     }
-}
\ No newline at end of file
+
+    /// The stored tiles drawn with the LDtk flips look like the pyxel tile drawn with its
+    /// transformation, also when the tileset stores flipped copies (`TileDedupe::Flipped`)
+    #[test]
+    fn tile_ref_transforms_draw_the_pyxel_tile() {
+        // 2x2 tile without any symmetry
+        let tile = RgbaImage::from_fn(2, 2, |x, y| Rgba([(x + 2 * y) as u8 * 60, 0, 0, 255]));
+        for flip_x in [false, true].iter().cloned() {
+            for rot in 0..4 {
+                // pyxel mirrors the tile first, then rotates it clockwise
+                let mut expected = if flip_x { imageops::flip_horizontal(&tile) } else { tile.clone() };
+                for _ in 0..rot {
+                    expected = imageops::rotate90(&expected);
+                }
+
+                // stored tiles: the tile and its rotated copy, flipped by the tile dedupe
+                for stored_flip in 0..4 {
+                    let stored = [ldtk_flip(&tile, stored_flip), ldtk_flip(&imageops::rotate90(&tile), stored_flip)];
+                    let doc_tiles = DocTiles {
+                        tile_ids: vec![(7, AtlasTile { tile_id: 0, flip: stored_flip })].into_iter().collect(),
+                        rotated_tiles: vec![(7, AtlasTile { tile_id: 1, flip: stored_flip })].into_iter().collect(),
+                    };
+                    let tile_ref = TileRef { index: 7, flip_x, rot };
+                    let (tile_id, flip) = tile_ref_ldtk_tile(&doc_tiles, &tile_ref).unwrap();
+                    let drawn = ldtk_flip(&stored[tile_id as usize], flip);
+                    assert_eq!(drawn, expected, "flipX={} rot={} stored flip={}", flip_x, rot, stored_flip);
+                }
+            }
+        }
+    }
+}
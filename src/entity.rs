@@ -0,0 +1,84 @@
+// LDtk entities painted as tiles.
+//
+// Spawn points, items or enemies are painted with tiles on a dedicated pyxel layer. Its tiles
+// become entity instances of an Entities layer, using a mapping from pyxel tile index to entity.
+
+use serde::*;
+use std::collections::BTreeMap;
+use crate::pyxel::{Layer, TileRef};
+
+/// Entity settings, usually read from a JSON sidecar file:
+///
+/// ```json
+/// {
+///   "layer": "Entities",
+///   "sourceLayer": "entities",
+///   "tiles": { "64": "PlayerStart", "65": "Coin", "80": "Enemy" }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EntityMap {
+    /// Identifier of the generated Entities layer
+    #[serde(default = "default_layer")]
+    pub layer: String,
+    /// Name of the layer holding the entity tiles, after the groups are resolved (`Group/Layer`
+    /// with `LayerGroups::Prefix`). It is not converted as a tile layer.
+    #[serde(rename = "sourceLayer", default = "default_source_layer")]
+    pub source_layer: String,
+    /// Pyxel tile index -> entity identifier
+    #[serde(default)]
+    pub tiles: BTreeMap<i64, String>,
+}
+
+fn default_layer() -> String {
+    "Entities".to_owned()
+}
+
+fn default_source_layer() -> String {
+    "entities".to_owned()
+}
+
+impl Default for EntityMap {
+    fn default() -> EntityMap {
+        EntityMap {
+            layer: default_layer(),
+            source_layer: default_source_layer(),
+            tiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl EntityMap {
+    /// Whether a pyxel layer is the entity source layer
+    pub fn is_source_layer(&self, layer: &Layer) -> bool {
+        self.source_layer == layer.name
+    }
+
+    /// Entity identifiers, each with the first pyxel tile index using it (its editor tile)
+    pub fn entity_tiles(&self) -> Vec<(&str, i64)> {
+        let mut entities: Vec<(&str, i64)> = vec![];
+        for (&index, identifier) in self.tiles.iter() {
+            if !entities.iter().any(|&(known, _)| known == identifier) {
+                entities.push((identifier, index));
+            }
+        }
+        entities
+    }
+
+    /// Entity tiles of a document: (cell, entity identifier, tile reference), in layer order.
+    /// `layers` are the tile layers of the document, with the groups resolved.
+    /// Also returns the number of tiles which are not in the mapping.
+    pub fn entity_refs<'a>(&'a self, layers: &'a [Layer]) -> (Vec<(i64, &'a str, &'a TileRef)>, usize) {
+        let mut refs = vec![];
+        let mut unlisted = 0;
+        for layer in layers.iter().filter(|layer| self.is_source_layer(layer)) {
+            for (&cell, tile_ref) in layer.tile_refs.iter() {
+                match self.tiles.get(&tile_ref.index) {
+                    Some(identifier) => refs.push((cell, identifier.as_str(), tile_ref)),
+                    None => unlisted += 1,
+                }
+            }
+        }
+        (refs, unlisted)
+    }
+}
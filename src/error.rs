@@ -0,0 +1,97 @@
+// Errors returned by the converter.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, ConvertError>;
+
+#[derive(Debug)]
+pub enum ConvertError {
+    /// File system error on `path`
+    Io { path: PathBuf, source: io::Error },
+    /// The .pyxel file is not a valid zip archive
+    Zip { path: String, source: zip::result::ZipError },
+    /// An expected file is missing from the .pyxel archive (eg. `docData.json`, `tile12.png`)
+    MissingEntry { entry: String },
+    /// `docData.json` does not match the expected structure. `pointer` is the JSON pointer
+    /// of the offending field (eg. `/canvas/layers/0/tileRefs`)
+    Json { entry: String, pointer: String, message: String },
+    /// A tile image of the archive cannot be decoded
+    Image { entry: String, source: image::ImageError },
+    /// The document uses something that cannot be represented in LDtk
+    Unsupported { message: String },
+}
+
+impl ConvertError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> ConvertError {
+        ConvertError::Io { path: path.into(), source }
+    }
+
+    pub fn zip(path: impl Into<String>, source: zip::result::ZipError) -> ConvertError {
+        match source {
+            zip::result::ZipError::FileNotFound => ConvertError::MissingEntry { entry: path.into() },
+            zip::result::ZipError::Io(source) => ConvertError::io(path.into(), source),
+            source => ConvertError::Zip { path: path.into(), source },
+        }
+    }
+
+    /// JSON deserialization error, with the JSON pointer of the offending field
+    pub fn json(entry: impl Into<String>, err: serde_path_to_error::Error<serde_json::Error>) -> ConvertError {
+        let pointer: String = err
+            .path()
+            .iter()
+            .map(|segment| match segment {
+                serde_path_to_error::Segment::Seq { index } => format!("/{}", index),
+                serde_path_to_error::Segment::Map { key } => format!("/{}", key),
+                serde_path_to_error::Segment::Enum { variant } => format!("/{}", variant),
+                serde_path_to_error::Segment::Unknown => "/?".to_owned(),
+            })
+            .collect();
+        ConvertError::Json { entry: entry.into(), pointer, message: err.into_inner().to_string() }
+    }
+
+    pub fn unsupported(message: impl Into<String>) -> ConvertError {
+        ConvertError::Unsupported { message: message.into() }
+    }
+
+    /// Prefix archive entries with the archive path (eg. `level1.pyxel/docData.json`),
+    /// to know which file failed when converting several documents
+    pub fn in_archive(self, archive: &str) -> ConvertError {
+        let prefixed = |entry: String| format!("{}/{}", archive, entry);
+        match self {
+            ConvertError::MissingEntry { entry } => ConvertError::MissingEntry { entry: prefixed(entry) },
+            ConvertError::Json { entry, pointer, message } => {
+                ConvertError::Json { entry: prefixed(entry), pointer, message }
+            }
+            ConvertError::Image { entry, source } => ConvertError::Image { entry: prefixed(entry), source },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConvertError::Zip { path, source } => write!(f, "{}: invalid archive: {}", path, source),
+            ConvertError::MissingEntry { entry } => write!(f, "{}: missing from the archive", entry),
+            ConvertError::Json { entry, pointer, message } => {
+                write!(f, "{} at '{}': {}", entry, pointer, message)
+            }
+            ConvertError::Image { entry, source } => write!(f, "{}: {}", entry, source),
+            ConvertError::Unsupported { message } => write!(f, "unsupported: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConvertError::Io { source, .. } => Some(source),
+            ConvertError::Zip { source, .. } => Some(source),
+            ConvertError::Image { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
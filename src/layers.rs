@@ -0,0 +1,90 @@
+// Pyxel Edit layer groups.
+//
+// Layers are nested in groups with `parentIndex`. LDtk has no layer groups, so the groups are
+// resolved into the plain tile layers which are converted.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use crate::pyxel::{Layer, PyxelDoc};
+use crate::{ConvertOptions, LayerGroups};
+
+/// Tile layers of a pyxel document, top-most first, with the groups resolved
+/// according to the conversion options
+pub fn tile_layers(doc: &PyxelDoc, options: &ConvertOptions, warnings: &mut Vec<String>) -> Vec<Layer> {
+    let layers = &doc.canvas.layers;
+    let mut tile_layers: Vec<Layer> = vec![];
+    // collapsed group index -> position in tile_layers
+    let mut collapsed: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut hidden_tiles = 0;
+
+    for (&index, layer) in layers.iter() {
+        if is_group(layers, index) {
+            continue;
+        }
+        let groups = ancestors(layers, index);
+
+        if let (LayerGroups::Collapse, Some(&outer)) = (options.layer_groups, groups.last()) {
+            // the group becomes a single layer, upper children cover the lower ones
+            if layer.hidden {
+                hidden_tiles += layer.tile_refs.len();
+                continue;
+            }
+            let pos = *collapsed.entry(outer).or_insert_with(|| {
+                let group = &layers[&outer];
+                tile_layers.push(Layer { tile_refs: BTreeMap::new(), ..group.clone() });
+                tile_layers.len() - 1
+            });
+            let merged = &mut tile_layers[pos];
+            for (&cell, tile_ref) in layer.tile_refs.iter() {
+                match merged.tile_refs.entry(cell) {
+                    Entry::Occupied(_) => hidden_tiles += 1,
+                    Entry::Vacant(entry) => {
+                        entry.insert(tile_ref.clone());
+                    }
+                }
+            }
+            continue;
+        }
+
+        let mut tile_layer = layer.clone();
+        if options.inherit_group_visibility {
+            for group in groups.iter().map(|group| &layers[group]) {
+                tile_layer.hidden |= group.hidden;
+                tile_layer.muted |= group.muted;
+                tile_layer.alpha = (u32::from(tile_layer.alpha) * u32::from(group.alpha) / 255) as u8;
+            }
+        }
+        if options.layer_groups == LayerGroups::Prefix {
+            let mut names: Vec<&str> = groups.iter().rev().map(|group| layers[group].name.as_str()).collect();
+            names.push(&layer.name);
+            tile_layer.name = names.join("/");
+        }
+        tile_layers.push(tile_layer);
+    }
+
+    if hidden_tiles > 0 {
+        warnings.push(format!(
+            "'{}': {} tiles of collapsed groups are hidden by the layers above them or in a hidden layer",
+            doc.name, hidden_tiles
+        ));
+    }
+    tile_layers
+}
+
+/// Whether a layer is a group: by its type, or because other layers are in it
+pub fn is_group(layers: &BTreeMap<usize, Layer>, index: usize) -> bool {
+    layers[&index].layer_type.contains("group") || layers.values().any(|layer| layer.parent_index == index as i64)
+}
+
+/// Indices of the groups containing a layer, innermost first
+fn ancestors(layers: &BTreeMap<usize, Layer>, index: usize) -> Vec<usize> {
+    let mut groups: Vec<usize> = vec![];
+    let mut parent = layers[&index].parent_index;
+    // stop on unknown parents and on loops
+    while parent >= 0 && layers.contains_key(&(parent as usize)) && !groups.contains(&(parent as usize)) {
+        let group = parent as usize;
+        groups.push(group);
+        parent = layers[&group].parent_index;
+    }
+    groups
+}
@@ -0,0 +1,327 @@
+//! Convert Pyxel Edit (.pyxel) documents to LDtk projects.
+//!
+//! ```no_run
+//! use pyxeledit2ldtk::{ConvertOptions, Converter};
+//!
+//! let file = std::fs::File::open("level.pyxel").unwrap();
+//! let output = Converter::new(ConvertOptions::default()).convert(file).unwrap();
+//! output.tilesets[0].image.save("level.png").unwrap();
+//! ```
+
+pub mod ldtk;
+pub mod pyxel;
+mod animation;
+mod collision;
+mod config;
+mod convert;
+mod entity;
+mod error;
+mod layers;
+mod palette;
+mod tileset;
+mod uid;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Seek};
+use std::path::Path;
+use image::RgbaImage;
+use serde::de::DeserializeOwned;
+use zip::ZipArchive;
+use crate::animation::tile_animations;
+use crate::convert::{convert, get_pyxel_doc};
+use crate::palette::palette_colors;
+use crate::error::Result;
+use crate::ldtk::{Ldtk, WorldLayout};
+use crate::pyxel::PyxelDoc;
+use crate::tileset::{
+    build_tileset_image, page_rel_path, read_layer_images, read_tile_images, slice_pixel_layers, TileImages,
+};
+
+pub use crate::animation::{AnimationFrame, TileAnimation};
+pub use crate::collision::{CollisionMap, CollisionValue};
+pub use crate::config::{Config, LayerSettings, OutputSettings, ProjectSettings, CONFIG_FILE_NAME};
+pub use crate::convert::LDTK_JSON_VERSION;
+pub use crate::entity::EntityMap;
+pub use crate::error::ConvertError;
+pub use crate::palette::{export_palette, PaletteColor, PaletteFormat};
+pub use crate::tileset::AtlasTile;
+
+/// Conversion settings
+#[derive(Clone)]
+pub struct ConvertOptions {
+    /// Tileset identifier, defaults to the Pyxel Edit document name
+    pub tileset_name: Option<String>,
+    /// Path of the tileset image, relative to the LDtk file. Defaults to `<tileset name>.png`
+    pub tileset_rel_path: Option<String>,
+    /// How levels are placed in the world when converting several documents
+    pub world_layout: WorldLayout,
+    /// How identical tiles are merged in the tileset image.
+    /// Defaults to `Exact` when converting several documents, `None` otherwise
+    pub tile_dedupe: Option<TileDedupe>,
+    /// Space in pixels between the tileset image borders and the tiles
+    pub padding: u32,
+    /// Space in pixels between two tiles of the tileset image
+    pub spacing: u32,
+    /// Number of pixels the tile edges are repeated around each tile, against texture bleeding.
+    /// The extrusion is added to the padding and the spacing written in the LDtk project
+    pub extrude: u32,
+    /// Use power-of-two sizes for the tileset image, the number of tiles per row is chosen
+    /// to get the smallest image
+    pub power_of_two: bool,
+    /// Maximum width and height of the tileset image, in pixels
+    pub max_atlas_size: Option<u32>,
+    /// When the tiles don't fit in `max_atlas_size`, split the tileset into several images
+    /// (one LDtk tileset each, named `<tileset name>_2`, ...) instead of failing.
+    /// Layers using tiles of several images are split into one layer per image, named like the
+    /// images (`<layer>`, `<layer>_2`, ...).
+    pub split_atlas: bool,
+    /// Leave out the layers hidden or muted in Pyxel Edit
+    pub skip_hidden_layers: bool,
+    /// How the layers in Pyxel Edit groups are converted
+    pub layer_groups: LayerGroups,
+    /// Layers in a group are also hidden, muted and transparent when the group is
+    pub inherit_group_visibility: bool,
+    /// Also convert the pixels painted in the layers outside of tiles: they are cut into
+    /// tile-sized cells, added to the tileset when they don't match an existing tile
+    pub pixel_layers: bool,
+    /// Identifier of an LDtk enum with one value per Pyxel Edit animation (its first frame is
+    /// the value icon). None to leave the animations out of the project
+    pub animation_enum: Option<String>,
+    /// Identifier of an IntGrid layer with one value per color of the Pyxel Edit palette
+    /// (value N has the color of the palette slot N, counted from 1). None to not create it
+    pub palette_int_grid: Option<String>,
+    /// IntGrid collision layer generated from the tiles (see `read_collision_file`).
+    /// None to not create it
+    pub collision: Option<CollisionMap>,
+    /// Entities layer generated from the tiles of a pyxel layer (see `read_entity_file`).
+    /// None to convert that layer as a tile layer
+    pub entities: Option<EntityMap>,
+    /// Settings of a new LDtk project
+    pub project: ProjectSettings,
+    /// Layer name, with the groups resolved -> how the layer is converted (renamed, skipped,
+    /// layer type). See `Config::layers`
+    pub layers: BTreeMap<String, LayerSettings>,
+}
+
+/// How the layers in Pyxel Edit groups are converted (LDtk has no layer groups)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LayerGroups {
+    /// Every layer of a group is converted with its own name
+    Flatten,
+    /// Every layer of a group is converted, named `Group/Layer`
+    Prefix,
+    /// All the layers of a group are merged into a single layer named after the group
+    Collapse,
+}
+
+/// How identical tiles are merged in the tileset image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TileDedupe {
+    /// Every pyxel tile gets its own place in the tileset image
+    None,
+    /// Tiles with exactly the same pixels are stored once
+    Exact,
+    /// Tiles that are a flipped (or 180° rotated) copy of a stored tile reuse it with LDtk flip bits
+    Flipped,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> ConvertOptions {
+        ConvertOptions {
+            tileset_name: None,
+            tileset_rel_path: None,
+            world_layout: WorldLayout::Free,
+            tile_dedupe: None,
+            padding: 0,
+            spacing: 0,
+            extrude: 0,
+            power_of_two: false,
+            max_atlas_size: None,
+            split_atlas: false,
+            skip_hidden_layers: false,
+            layer_groups: LayerGroups::Flatten,
+            inherit_group_visibility: false,
+            pixel_layers: false,
+            animation_enum: None,
+            palette_int_grid: None,
+            collision: None,
+            entities: None,
+            project: ProjectSettings::default(),
+            layers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Result of a conversion, kept in memory
+pub struct ConversionOutput {
+    /// The LDtk project
+    pub ldtk: Ldtk,
+    /// The tileset images referenced by the LDtk project, a single one unless the tileset was split
+    pub tilesets: Vec<TilesetImage>,
+    /// Non fatal conversion issues
+    pub warnings: Vec<String>,
+    /// Pyxel tile index -> tile of the tileset image, for each document in conversion order
+    pub tile_mapping: Vec<BTreeMap<i64, AtlasTile>>,
+    /// Pyxel Edit animations, with their frames in the tileset images
+    pub animations: Vec<TileAnimation>,
+    /// Colors of the Pyxel Edit palette (of the first document having one), see `export_palette`
+    pub palette: Vec<PaletteColor>,
+}
+
+/// A tileset image of the conversion
+pub struct TilesetImage {
+    /// Path of the image relative to the LDtk file, as written in the project
+    pub rel_path: String,
+    pub image: RgbaImage,
+}
+
+/// Pyxel Edit to LDtk converter
+pub struct Converter {
+    options: ConvertOptions,
+    /// existing project to merge the conversion into
+    base: Option<Ldtk>,
+}
+
+impl Converter {
+    pub fn new(options: ConvertOptions) -> Converter {
+        Converter { options, base: None }
+    }
+
+    /// Merge the conversion into an existing LDtk project instead of creating a new one.
+    /// The tileset, layer definitions and levels with the same identifiers as the converted
+    /// ones are replaced, everything else (entities, enums, fields, other levels and layers)
+    /// is kept, and new uids start from the project `next_uid`.
+    pub fn merge_into(mut self, project: Ldtk) -> Converter {
+        self.base = Some(project);
+        self
+    }
+
+    /// Convert a Pyxel Edit document (the .pyxel zip archive)
+    pub fn convert<R: Read + Seek>(&self, reader: R) -> Result<ConversionOutput> {
+        self.convert_all(vec![reader])
+    }
+
+    /// Convert several Pyxel Edit documents into a single LDtk project, one level per document.
+    /// The tileset is shared and tiles used by several documents are stored only once.
+    pub fn convert_all<R: Read + Seek>(&self, readers: Vec<R>) -> Result<ConversionOutput> {
+        let mut sources = vec![];
+        let mut warnings = vec![];
+        for (ri, reader) in readers.into_iter().enumerate() {
+            let name = format!("<archive #{}>", ri);
+            let mut archive = ZipArchive::new(reader).map_err(|err| ConvertError::zip(&name, err))?;
+            let source = load_archive(&mut archive, &self.options, &mut warnings).map_err(|err| err.in_archive(&name))?;
+            sources.push(source);
+        }
+        self.convert_sources(&sources, warnings)
+    }
+
+    /// Convert a Pyxel Edit file
+    pub fn convert_file(&self, path: &Path) -> Result<ConversionOutput> {
+        self.convert_files(&[path])
+    }
+
+    /// Convert several Pyxel Edit files into a single LDtk project (see `convert_all`)
+    pub fn convert_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<ConversionOutput> {
+        let mut sources = vec![];
+        let mut warnings = vec![];
+        for path in paths.iter() {
+            let path = path.as_ref();
+            let name = path.display().to_string();
+            let file = fs::File::open(path).map_err(|err| ConvertError::io(path, err))?;
+            let mut archive = ZipArchive::new(file).map_err(|err| ConvertError::zip(&name, err))?;
+            let source = load_archive(&mut archive, &self.options, &mut warnings).map_err(|err| err.in_archive(&name))?;
+            sources.push(source);
+        }
+        self.convert_sources(&sources, warnings)
+    }
+
+    /// Convert the loaded documents, `warnings` are the ones of the loading
+    fn convert_sources(
+        &self,
+        sources: &[(PyxelDoc, TileImages)],
+        mut warnings: Vec<String>,
+    ) -> Result<ConversionOutput> {
+        let docs: Vec<&PyxelDoc> = sources.iter().map(|(doc, _)| doc).collect();
+        let first_doc = docs.first().ok_or_else(|| ConvertError::unsupported("no document to convert"))?;
+
+        let tileset_name = match &self.options.tileset_name {
+            Some(name) => name.to_owned(),
+            None => first_doc.name.to_owned(),
+        };
+        let tileset_rel_path = match &self.options.tileset_rel_path {
+            Some(rel_path) => rel_path.to_owned(),
+            None => format!("{}.png", tileset_name),
+        };
+
+        // a shared tileset only stores the tiles common to several documents once
+        let dedupe = match self.options.tile_dedupe {
+            Some(dedupe) => dedupe,
+            None if sources.len() > 1 => TileDedupe::Exact,
+            None => TileDedupe::None,
+        };
+        let tile_sources: Vec<(&PyxelDoc, &TileImages)> = sources.iter().map(|(doc, tiles)| (doc, tiles)).collect();
+
+        let (images, data) = build_tileset_image(&tile_sources, &tileset_name, dedupe, &self.options, &mut warnings)?;
+        let animations = tile_animations(&docs, &data, &mut warnings);
+        let base = self.base.clone();
+        let ldtk = convert(&docs, &data, &tileset_rel_path, &self.options, base, &animations, &mut warnings)?;
+        let tile_mapping = data.docs.iter().map(|doc_tiles| doc_tiles.tile_ids.iter().map(|(&index, &tile)| (index, tile)).collect()).collect();
+
+        let tilesets = images
+            .into_iter()
+            .enumerate()
+            .map(|(page, image)| TilesetImage { rel_path: page_rel_path(&tileset_rel_path, page), image })
+            .collect();
+
+        let palette = docs.iter().find_map(|doc| doc.palette.as_ref()).map(palette_colors).unwrap_or_default();
+
+        Ok(ConversionOutput { ldtk, tilesets, warnings, tile_mapping, animations, palette })
+    }
+}
+
+/// Read a JSON file into `T`, errors point at the mismatching JSON value
+fn read_json_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let json = fs::read_to_string(path).map_err(|err| ConvertError::io(path, err))?;
+    let de = &mut serde_json::Deserializer::from_str(&json);
+    serde_path_to_error::deserialize(de).map_err(|err| ConvertError::json(path.display().to_string(), err))
+}
+
+/// Read an existing LDtk project file
+pub fn read_ldtk_file(path: &Path) -> Result<Ldtk> {
+    read_json_file(path)
+}
+
+/// Read a collision sidecar file (JSON, see `CollisionMap`)
+pub fn read_collision_file(path: &Path) -> Result<CollisionMap> {
+    read_json_file(path)
+}
+
+/// Read an entity sidecar file (JSON, see `EntityMap`)
+pub fn read_entity_file(path: &Path) -> Result<EntityMap> {
+    read_json_file(path)
+}
+
+/// Read a config file (JSON, see `Config`)
+pub fn read_config_file(path: &Path) -> Result<Config> {
+    read_json_file(path)
+}
+
+/// Read the document and the tile images of a .pyxel archive
+fn load_archive<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    options: &ConvertOptions,
+    warnings: &mut Vec<String>,
+) -> Result<(PyxelDoc, TileImages)> {
+    let mut doc = get_pyxel_doc(archive)?;
+    let mut tile_images = read_tile_images(archive)?;
+    if options.pixel_layers {
+        let layer_images = read_layer_images(archive)?;
+        let new_tiles = slice_pixel_layers(&mut doc, &mut tile_images, &layer_images);
+        if new_tiles > 0 {
+            let message = format!("'{}': {} new tiles cut from the pixels painted outside of tiles", doc.name, new_tiles);
+            warnings.push(message);
+        }
+    }
+    Ok((doc, tile_images))
+}
@@ -1,151 +1,400 @@
-mod ldtk;
-mod convert;
+mod cli;
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::io;
 use std::env;
+use std::io;
 use std::path::{Path, PathBuf};
-use image::{RgbaImage, GenericImage, open};
-use convert::convert;
-use crate::convert::get_pyxel_json_map;
-
-pub struct SharedData {
-    tileset_filename: String,
-    tileset_w: i64,
-    tileset_h: i64,
-    tile_w: i64,
-    tile_h: i64,
+use std::thread;
+use std::time::{Duration, SystemTime};
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+use pyxeledit2ldtk::{
+    export_palette, read_collision_file, read_config_file, read_entity_file, read_ldtk_file, AtlasTile, CollisionMap,
+    Config, ConvertError, ConvertOptions, Converter, EntityMap, PaletteFormat, CONFIG_FILE_NAME,
+};
+use crate::cli::{parse_args, Args, Command, Verbosity, USAGE};
+
+/// Process exit code for each kind of error
+fn exit_code(err: &ConvertError) -> i32 {
+    match err {
+        ConvertError::Io { .. } => 2,
+        ConvertError::Zip { .. } => 3,
+        ConvertError::MissingEntry { .. } => 4,
+        ConvertError::Json { .. } => 5,
+        ConvertError::Image { .. } => 6,
+        ConvertError::Unsupported { .. } => 7,
+    }
 }
+
 // ---------------------------------------
 fn main() {
     std::process::exit(real_main());
 }
 
-fn build_tileset_image(path: &Path) -> SharedData {
-    println!("------------ TILESET IMAGE -------------");
-    let temp_dir = env::temp_dir();
-    println!("Temporary directory: {}", temp_dir.display());
-
-    let mut tiles: Vec<&str> = Vec::new(); // tile filenames (no folder)
-
-    let _source_filename = path.to_str().unwrap();
-    let source_file = path.file_name().unwrap().to_os_string().into_string().unwrap();
-    let source_name = path.file_stem().unwrap().to_os_string().into_string().unwrap();
-
-    println!("source file = {}",source_file);
-    print!(">>> Reading file {:?}\n", path.as_os_str());
-
-    // [1] Open pyxel archive file (pyxel extension - a zip file)
-    let pyxel = fs::File::open(&path).unwrap();
-    let archive = zip::ZipArchive::new(pyxel).unwrap();
-
-    // [3] Store tiles' names
-    let all_files: Vec<&str> = archive.file_names().collect();
-    for current in all_files {
-        if current.starts_with("tile") {
-            tiles.push(current.clone());
-        }
-    }
-
-    // [2] Sort tiles by number
-    tiles.sort_by(|a, b| {
-        // filename is "tileXXX.png", so compare only the numeric part
-        let a1 = a.split('.').next().unwrap_or("");
-        let b1 = b.split('.').next().unwrap_or("");
-        let a2: i32 = a1[4..].parse().unwrap();
-        let b2: i32 = b1[4..].parse().unwrap();
-        a2.cmp(&b2)
-    });
-
-    // [3] UNZIP files to a temp folder
-    let mut path_dest = PathBuf::new();
-    path_dest.push(temp_dir.clone());
-    path_dest.push("tmp-pyxel");
-    fs::create_dir_all(path_dest.clone()).unwrap();
-
-    let file = fs::File::open(path).unwrap();
-    let mut archive2 = zip::ZipArchive::new(file).unwrap();
-    for ti in 0..tiles.len() {
-        let tile = tiles[ti];
-        let mut file = archive2.by_name(tile).unwrap();
-
-        let mut outp = path_dest.clone();
-        match file.enclosed_name() {
-            Some(path) => outp.push(path.to_owned()),
-            None => continue,
-        };
-        {
-            let mut outfile = fs::File::create(&outp).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
-        }
-    }
-
-    // get tile width and height from Pyxel Edit (json)
-    let json = get_pyxel_json_map(path);
-    let tileset = json["tileset"].as_object().unwrap();
-    let tiles_per_row = tileset["tilesWide"].as_u64().unwrap();
-    let canvas = json["canvas"].as_object().unwrap();
-    let tile_w = canvas["tileWidth"].as_u64().unwrap();
-    let tile_h = canvas["tileHeight"].as_u64().unwrap();
-    print!("pyxel tile width={} height={}\n",tile_w,tile_h);
-
-    // [4] CREATE DEST image
-    let num_tiles: u32 = tiles.len() as u32;
-    let dest_x = tiles_per_row * tile_w;
-    let dest_y= ((num_tiles / tiles_per_row as u32) + 1) * tile_w as u32;
-    println!("dest image w={} h={}", dest_x, dest_y);
-    let mut dest_img: RgbaImage = RgbaImage::new(dest_x as u32, dest_y as u32);
-
-    // [5] READING files from TEMP folder
-    let mut x = 0;
-    let mut y= 0;
-    for ti in 0..tiles.len() {
-        let mut path = PathBuf::new();
-        path.push(path_dest.clone());
-        path.push(tiles[ti]);
-        let curr_tile = open(&path).unwrap().into_rgba8();
-        // println!("dimensions x={} y={}", curr_tile.width(), curr_tile.height());
-        dest_img.copy_from(&curr_tile, x, y).expect("copy_from error");
-        x += tile_w as u32;
-        if x >= dest_x as u32 {
-            y += tile_w as u32;
-            x = 0;
-        }
-    }
-
-    // [6] DESTINATION PATH
-    let mut dest_path = PathBuf::new();
-    dest_path.push("target");
-    dest_path.push(source_name.to_owned());
-    dest_path.set_extension("png");
-
-    // [7] SAVE image
-    dest_img.save(dest_path).unwrap();
-
-    // [8] remove temp dir
-    fs::remove_dir_all(path_dest).expect("Remove dir with error");
-
-    SharedData {
-        tileset_filename: source_name.clone(),
-        tileset_w: dest_x as i64,
-        tileset_h: dest_y as i64,
-        tile_w: tile_w as i64,
-        tile_h: tile_h as i64,
+// -----------------------------------------
+fn real_main() -> i32 {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Command::Convert(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Ok(Command::Version) => {
+            println!("pyxeledit2ldtk {}", env!("CARGO_PKG_VERSION"));
+            return 0;
+        }
+        Err(err) => {
+            eprintln!("\x1b[0;31m-- {} --\x1b[0m\n\n{}", err, USAGE);
+            return 1;
+        }
+    };
+
+    if args.watch {
+        watch(&args)
+    }
+    match run(&args, false) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("\x1b[0;31m-- {} --\x1b[0m", err);
+            exit_code(&err)
+        }
     }
 }
 
-// -----------------------------------------
-fn real_main() -> i32 {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("\x1b[0;31m-- No arguments --\x1b[0m");
-        return 1;
+/// Convert the sources, `overwrite` allows replacing existing output files
+/// (the outputs of a previous run in watch mode)
+fn run(args: &Args, overwrite: bool) -> Result<(), ConvertError> {
+    let sources = collect_sources(&args.sources)?;
+    let config_path = config_path(args);
+    let config = match &config_path {
+        Some(path) => {
+            if args.verbosity >= Verbosity::Normal {
+                println!(">>> Reading config {:?}", path.as_os_str());
+            }
+            read_config_file(path)?
+        }
+        None => Config::default(),
+    };
+
+    // command line options first, then the config file outputs
+    let source_name = match (args.sources.len(), args.sources[0].file_stem()) {
+        (1, Some(stem)) => stem.to_string_lossy().into_owned(),
+        _ => "world".to_owned(),
+    };
+    let out_dir = match (&args.out_dir, &config.output.out_dir, &config_path) {
+        (Some(out_dir), _, _) => out_dir.to_owned(),
+        (None, Some(out_dir), Some(path)) => path.parent().unwrap_or_else(|| Path::new("")).join(out_dir),
+        _ => PathBuf::from("."),
+    };
+    let ldtk_name = args.ldtk_name.clone().or_else(|| config.output.ldtk_name.clone()).unwrap_or(source_name);
+    let tileset_name = args
+        .tileset_name
+        .clone()
+        .or_else(|| config.output.tileset_name.clone())
+        .unwrap_or_else(|| ldtk_name.to_owned());
+    let tileset_rel_path = args
+        .tileset_rel_path
+        .clone()
+        .or_else(|| config.output.tileset_rel_path.clone())
+        .unwrap_or_else(|| format!("{}.png", tileset_name));
+
+    // the tileset image is written where the LDtk file expects it
+    let ldtk_path = out_dir.join(format!("{}.ldtk", ldtk_name));
+    let tileset_path = out_dir.join(&tileset_rel_path);
+    // merging rewrites the LDtk file and the tileset image it owns
+    let merge = args.merge && ldtk_path.exists();
+    let overwrite = args.force || merge || overwrite;
+    check_overwrite(&ldtk_path, overwrite)?;
+    check_overwrite(&tileset_path, overwrite)?;
+
+    if args.verbosity >= Verbosity::Normal {
+        for source in sources.iter() {
+            println!(">>> Reading file {:?}", source.as_os_str());
+        }
+    }
+    let options = ConvertOptions {
+        tileset_name: Some(tileset_name),
+        tileset_rel_path: Some(tileset_rel_path),
+        world_layout: args.world_layout,
+        tile_dedupe: args.tile_dedupe,
+        padding: args.padding,
+        spacing: args.spacing,
+        extrude: args.extrude,
+        power_of_two: args.power_of_two,
+        max_atlas_size: args.max_atlas_size,
+        split_atlas: args.split_atlas,
+        skip_hidden_layers: args.skip_hidden_layers,
+        layer_groups: args.layer_groups,
+        inherit_group_visibility: args.inherit_group_visibility,
+        pixel_layers: args.pixel_layers,
+        animation_enum: args.animation_enum.clone(),
+        palette_int_grid: args.palette_int_grid.clone(),
+        collision: collision_map(args)?,
+        entities: entity_map(args)?,
+        project: config.project,
+        layers: config.layers,
+    };
+    let mut converter = Converter::new(options);
+    if merge {
+        if args.verbosity >= Verbosity::Normal {
+            println!(">>> Merging into {:?}", ldtk_path.as_os_str());
+        }
+        converter = converter.merge_into(read_ldtk_file(&ldtk_path)?);
+    }
+    let output = converter.convert_files(&sources)?;
+    if args.verbosity >= Verbosity::Normal {
+        for warning in output.warnings.iter() {
+            println!("\x1b[0;33mWARNING: {}\x1b[0m", warning);
+        }
+    }
+    if args.verbosity >= Verbosity::Verbose {
+        report_tile_mapping(&sources, &output.tile_mapping);
+        for level in output.ldtk.levels.iter() {
+            let layers = level.layer_instances.as_ref().map_or(0, |layers| layers.len());
+            println!("level '{}': {}x{} px, {} layers", level.identifier, level.px_wid, level.px_hei, layers);
+        }
+        for tileset in output.tilesets.iter() {
+            println!("tileset image {}: {}x{} px", tileset.rel_path, tileset.image.width(), tileset.image.height());
+        }
+    }
+
+    // [] WRITE tileset images
+    // pages of a split tileset and animations are only known after the conversion
+    let animations_path = out_dir.join(format!("{}.animations.json", ldtk_name));
+    for tileset in output.tilesets.iter().skip(1) {
+        check_overwrite(&out_dir.join(&tileset.rel_path), overwrite)?;
+    }
+    if !output.animations.is_empty() {
+        check_overwrite(&animations_path, overwrite)?;
+    }
+    let palette_paths: Vec<(PaletteFormat, PathBuf)> = args
+        .palette_formats
+        .iter()
+        .map(|&format| (format, out_dir.join(format!("{}.{}", ldtk_name, format.extension()))))
+        .collect();
+    for (_, palette_path) in palette_paths.iter() {
+        check_overwrite(palette_path, overwrite)?;
+    }
+    for tileset in output.tilesets.iter() {
+        let tileset_path = out_dir.join(&tileset.rel_path);
+        let image = &tileset.image;
+        let mut png_save: Vec<u8> = vec![];
+        PngEncoder::new(&mut png_save)
+            .encode(image.as_raw(), image.width(), image.height(), ColorType::Rgba8)
+            .map_err(|source| ConvertError::Image {
+                entry: tileset_path.display().to_string(),
+                source,
+            })?;
+        write_output(&tileset_path, &png_save, args.verbosity)?;
+    }
+
+    // [] WRITE animations (json) file, for the game engine
+    if !output.animations.is_empty() {
+        let animations = serde_json::json!({ "animations": output.animations });
+        let json_save = serde_json::to_string_pretty(&animations).map_err(|err| ConvertError::Json {
+            entry: animations_path.display().to_string(),
+            pointer: "".to_owned(),
+            message: err.to_string(),
+        })?;
+        write_output(&animations_path, json_save.as_bytes(), args.verbosity)?;
+    }
+
+    // [] WRITE palette files
+    if !palette_paths.is_empty() && output.palette.is_empty() && args.verbosity >= Verbosity::Normal {
+        println!("\x1b[0;33mWARNING: no palette color to export\x1b[0m");
+    }
+    for (format, palette_path) in palette_paths.iter() {
+        let palette_save = export_palette(&output.palette, &ldtk_name, *format);
+        write_output(palette_path, palette_save.as_bytes(), args.verbosity)?;
+    }
+
+    // [] WRITE LDTK (json) file
+    let json_save = serde_json::to_string_pretty(&output.ldtk).map_err(|err| ConvertError::Json {
+        entry: ldtk_path.display().to_string(),
+        pointer: "".to_owned(),
+        message: err.to_string(),
+    })?;
+    write_output(&ldtk_path, json_save.as_bytes(), args.verbosity)?;
+
+    Ok(())
+}
+
+/// Config file: the --config one, or the one next to the first source (in it for a directory)
+fn config_path(args: &Args) -> Option<PathBuf> {
+    if let Some(path) = &args.config_file {
+        return Some(path.to_owned());
+    }
+    let source = &args.sources[0];
+    let dir = if source.is_dir() { source.as_path() } else { source.parent()? };
+    let path = dir.join(CONFIG_FILE_NAME);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Collision settings: the collision file, and the pyxel collision layer
+fn collision_map(args: &Args) -> Result<Option<CollisionMap>, ConvertError> {
+    let mut collision = match &args.collision_file {
+        Some(path) => Some(read_collision_file(path)?),
+        None => None,
+    };
+    if let Some(layer) = &args.collision_layer {
+        collision.get_or_insert_with(CollisionMap::default).source_layer = Some(layer.to_owned());
     }
-    let source_pyxeledit = &args[1];
+    Ok(collision)
+}
+
+/// Entity settings: the entity file, and the pyxel entity layer
+fn entity_map(args: &Args) -> Result<Option<EntityMap>, ConvertError> {
+    let mut entities = match &args.entity_file {
+        Some(path) => Some(read_entity_file(path)?),
+        None => None,
+    };
+    if let Some(layer) = &args.entity_layer {
+        entities.get_or_insert_with(EntityMap::default).source_layer = layer.to_owned();
+    }
+    Ok(entities)
+}
+
+/// Print the pyxel tiles sharing a tile of the tileset image
+fn report_tile_mapping(sources: &[PathBuf], tile_mapping: &[BTreeMap<i64, AtlasTile>]) {
+    let mut merged = 0;
+    for (source, tiles) in sources.iter().zip(tile_mapping.iter()) {
+        // tileset tile id -> pyxel tiles drawn with it
+        let mut shared: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        for (index, tile) in tiles.iter() {
+            let flip = match tile.flip {
+                0 => "",
+                1 => " (flip x)",
+                2 => " (flip y)",
+                _ => " (flip x+y)",
+            };
+            shared.entry(tile.tile_id).or_default().push(format!("{}{}", index, flip));
+        }
+        for (tile_id, indices) in shared.iter().filter(|(_, indices)| indices.len() > 1) {
+            println!("{}: tileset tile {} <- pyxel tiles {}", source.display(), tile_id, indices.join(", "));
+            merged += indices.len() - 1;
+        }
+    }
+    println!("{} duplicated tiles merged", merged);
+}
+
+/// Convert the sources each time they change, never returns
+fn watch(args: &Args) -> ! {
+    let interval = Duration::from_millis(args.poll_interval);
+    let mut overwrite = false;
+    let mut converted: Option<Vec<SourceState>> = None;
+    loop {
+        let state = sources_state(&watched_paths(args));
+        if converted.as_ref() != Some(&state) {
+            // debounce: wait until the sources stop changing (editors may save in several steps)
+            let mut pending = state;
+            loop {
+                thread::sleep(interval);
+                let state = sources_state(&watched_paths(args));
+                if state == pending {
+                    break;
+                }
+                pending = state;
+            }
+
+            match run(args, overwrite) {
+                Ok(()) => overwrite = true,
+                Err(err) => eprintln!("\x1b[0;31m-- {} --\x1b[0m", err),
+            }
+            if args.verbosity >= Verbosity::Normal {
+                println!(">>> Watching for changes (Ctrl+C to stop)");
+            }
+            converted = Some(pending);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Sources and config file, a change in any of them triggers a conversion
+fn watched_paths(args: &Args) -> Vec<PathBuf> {
+    let mut paths = args.sources.to_owned();
+    paths.extend(config_path(args));
+    paths
+}
 
-    let source_path = Path::new(source_pyxeledit);
-    let data = build_tileset_image(&source_path);
-    convert(&source_path, &data);
+/// Source file path, modification time and size
+type SourceState = (PathBuf, Option<SystemTime>, u64);
+
+/// Current state of the source files, used to detect changes in watch mode
+fn sources_state(paths: &[PathBuf]) -> Vec<SourceState> {
+    // when a directory can't be listed, the given paths themselves are watched
+    let sources = collect_sources(paths).unwrap_or_else(|_| paths.to_vec());
+    sources
+        .into_iter()
+        .map(|source| match fs::metadata(&source) {
+            Ok(meta) => (source, meta.modified().ok(), meta.len()),
+            Err(_) => (source, None, 0),
+        })
+        .collect()
+}
+
+/// Fail if an output file exists and may not be overwritten
+fn check_overwrite(path: &Path, overwrite: bool) -> Result<(), ConvertError> {
+    if !overwrite && path.exists() {
+        let err = io::Error::new(io::ErrorKind::AlreadyExists, "already exists (use --force to overwrite)");
+        return Err(ConvertError::io(path, err));
+    }
+    Ok(())
+}
+
+/// Write an output file, unless it already has exactly this content
+fn write_output(path: &Path, content: &[u8], verbosity: Verbosity) -> Result<(), ConvertError> {
+    if fs::read(path).is_ok_and(|existing| existing == content) {
+        if verbosity >= Verbosity::Verbose {
+            println!("{} is unchanged", path.display());
+        }
+        return Ok(());
+    }
+    create_parent_dir(path)?;
+    fs::write(path, content).map_err(|err| ConvertError::io(path, err))?;
+    if verbosity >= Verbosity::Normal {
+        println!("successfully wrote to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Source files, directories are replaced by the .pyxel files they contain
+fn collect_sources(paths: &[PathBuf]) -> Result<Vec<PathBuf>, ConvertError> {
+    let mut sources = vec![];
+    for path in paths.iter() {
+        if !path.is_dir() {
+            sources.push(path.to_owned());
+            continue;
+        }
+        let entries = fs::read_dir(path).map_err(|err| ConvertError::io(path, err))?;
+        let mut files = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|err| ConvertError::io(path, err))?;
+            let file = entry.path();
+            if file.is_file() && file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pyxel")) {
+                files.push(file);
+            }
+        }
+        if files.is_empty() {
+            let err = io::Error::new(io::ErrorKind::NotFound, "no .pyxel file in directory");
+            return Err(ConvertError::io(path, err));
+        }
+        files.sort();
+        sources.extend(files);
+    }
+    Ok(sources)
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), ConvertError> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => {
+            fs::create_dir_all(dir).map_err(|err| ConvertError::io(dir, err))
+        }
+        _ => Ok(()),
+    }
+}
 
-    return 0;
-}
\ No newline at end of file
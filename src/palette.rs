@@ -0,0 +1,106 @@
+// Pyxel Edit color palette.
+//
+// The palette colors are exported to standard palette files, and used as the colors of the
+// generated LDtk IntGrid values.
+
+use crate::ldtk::IntGridValueDefinition;
+use crate::pyxel::Palette;
+
+/// A color of the palette
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PaletteColor {
+    /// Slot of the color in the Pyxel Edit palette
+    pub slot: usize,
+    pub a: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl PaletteColor {
+    /// Parse a Pyxel Edit color (`aarrggbb` hex string)
+    pub fn from_argb(slot: usize, argb: &str) -> Option<PaletteColor> {
+        let argb = argb.trim_start_matches('#');
+        if argb.len() != 8 {
+            return None;
+        }
+        let value = u32::from_str_radix(argb, 16).ok()?;
+        let [a, r, g, b] = value.to_be_bytes();
+        Some(PaletteColor { slot, a, r, g, b })
+    }
+
+    /// `#rrggbb` color, as used by LDtk
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Palette file formats
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PaletteFormat {
+    /// GIMP palette (.gpl)
+    Gpl,
+    /// JASC palette (.pal)
+    Pal,
+    /// One `rrggbb` color per line (.hex)
+    Hex,
+}
+
+impl PaletteFormat {
+    /// File extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Pal => "pal",
+            PaletteFormat::Hex => "hex",
+        }
+    }
+}
+
+/// Colors of the palette in slot order, empty slots are left out
+pub fn palette_colors(palette: &Palette) -> Vec<PaletteColor> {
+    palette
+        .colors
+        .iter()
+        .filter_map(|(&slot, color)| PaletteColor::from_argb(slot, color.as_ref()?))
+        .collect()
+}
+
+/// Content of a palette file
+pub fn export_palette(colors: &[PaletteColor], name: &str, format: PaletteFormat) -> String {
+    match format {
+        PaletteFormat::Gpl => {
+            let mut gpl = format!("GIMP Palette\nName: {}\n#\n", name);
+            for color in colors.iter() {
+                gpl += &format!("{:3} {:3} {:3}\t{}\n", color.r, color.g, color.b, &color.hex()[1..]);
+            }
+            gpl
+        }
+        PaletteFormat::Pal => {
+            let mut pal = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+            for color in colors.iter() {
+                pal += &format!("{} {} {}\r\n", color.r, color.g, color.b);
+            }
+            pal
+        }
+        PaletteFormat::Hex => colors.iter().map(|color| format!("{}\n", &color.hex()[1..])).collect(),
+    }
+}
+
+/// IntGrid value of a palette color: value N has the color of the slot N of the palette
+/// (counted from 1, empty slots have no value)
+pub fn int_grid_value(color: &PaletteColor) -> i64 {
+    color.slot as i64 + 1
+}
+
+/// IntGrid values using the palette colors (see `int_grid_value`)
+pub fn int_grid_values(colors: &[PaletteColor]) -> Vec<IntGridValueDefinition> {
+    colors
+        .iter()
+        .map(|color| IntGridValueDefinition {
+            color: color.hex(),
+            identifier: Option::None,
+            value: int_grid_value(color),
+        })
+        .collect()
+}
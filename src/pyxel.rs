@@ -0,0 +1,185 @@
+// Typed model of the `docData.json` file stored inside a Pyxel Edit (.pyxel) archive.
+//
+// Only the parts of the document used by the converter are strictly required, everything
+// else falls back to a default value so that files saved by slightly different Pyxel Edit
+// versions can still be read.
+
+use serde::*;
+use std::collections::BTreeMap;
+
+use crate::error::{ConvertError, Result};
+
+/// Name of the document file inside the .pyxel archive
+pub const DOC_DATA_ENTRY: &str = "docData.json";
+
+/// Root of the Pyxel Edit `docData.json` file
+#[derive(Serialize, Deserialize)]
+pub struct PyxelDoc {
+    /// Document name (usually the .pyxel file stem)
+    pub name: String,
+    /// Pyxel Edit version that saved the document
+    pub version: String,
+    pub canvas: Canvas,
+    pub tileset: Tileset,
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    /// Animations, keyed by animation index
+    #[serde(default)]
+    pub animations: BTreeMap<usize, Animation>,
+    /// Editor settings (unused by the converter)
+    #[serde(default)]
+    pub settings: serde_json::Value,
+}
+
+/// Canvas (map) description
+#[derive(Serialize, Deserialize)]
+pub struct Canvas {
+    /// Canvas width in pixels
+    pub width: i64,
+    /// Canvas height in pixels
+    pub height: i64,
+    /// Tile width in pixels
+    #[serde(rename = "tileWidth")]
+    pub tile_width: i64,
+    /// Tile height in pixels
+    #[serde(rename = "tileHeight")]
+    pub tile_height: i64,
+    #[serde(rename = "numLayers")]
+    pub num_layers: usize,
+    #[serde(rename = "currentLayerIndex", default)]
+    pub current_layer_index: usize,
+    /// Layers, keyed by layer index (0 is the top-most layer)
+    pub layers: BTreeMap<usize, Layer>,
+}
+
+/// A single Pyxel Edit layer
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Layer {
+    pub name: String,
+    /// Layer type (eg. `tile_layer`)
+    #[serde(rename = "type")]
+    pub layer_type: String,
+    /// Layer opacity (0 to 255)
+    #[serde(default = "default_alpha")]
+    pub alpha: u8,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub soloed: bool,
+    #[serde(default)]
+    pub collapsed: bool,
+    /// Blend mode name (eg. `normal`, `multiply`)
+    #[serde(rename = "blendMode", default = "default_blend_mode")]
+    pub blend_mode: String,
+    /// Index of the parent group layer, -1 if the layer is not in a group
+    #[serde(rename = "parentIndex", default = "default_parent_index")]
+    pub parent_index: i64,
+    /// Tile references, keyed by cell position (`y * map_w + x`)
+    #[serde(rename = "tileRefs", default)]
+    pub tile_refs: BTreeMap<i64, TileRef>,
+}
+
+/// Reference to a tile of the tileset, placed in a layer cell
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TileRef {
+    /// Tile index in the tileset
+    pub index: i64,
+    /// Horizontal mirroring
+    #[serde(rename = "flipX", default)]
+    pub flip_x: bool,
+    /// Clockwise rotation in steps of 90 degrees (0 to 3)
+    #[serde(default)]
+    pub rot: u8,
+}
+
+/// Tileset description
+#[derive(Serialize, Deserialize)]
+pub struct Tileset {
+    /// Tile width in pixels
+    #[serde(rename = "tileWidth")]
+    pub tile_width: i64,
+    /// Tile height in pixels
+    #[serde(rename = "tileHeight")]
+    pub tile_height: i64,
+    #[serde(rename = "numTiles")]
+    pub num_tiles: usize,
+    /// Number of tiles per row in the Pyxel Edit tileset panel
+    #[serde(rename = "tilesWide")]
+    pub tiles_wide: i64,
+    #[serde(rename = "fixedWidth", default)]
+    pub fixed_width: bool,
+}
+
+/// Color palette
+#[derive(Serialize, Deserialize)]
+pub struct Palette {
+    #[serde(default)]
+    pub width: i64,
+    #[serde(default)]
+    pub height: i64,
+    #[serde(rename = "numColors", default)]
+    pub num_colors: usize,
+    /// ARGB hex colors (eg. `ff9d9d9d`), keyed by palette slot. Empty slots are `null`.
+    #[serde(default)]
+    pub colors: BTreeMap<usize, Option<String>>,
+}
+
+/// Tile animation
+#[derive(Serialize, Deserialize)]
+pub struct Animation {
+    pub name: String,
+    /// Tile index of the first frame
+    #[serde(rename = "baseTile")]
+    pub base_tile: i64,
+    /// Number of frames
+    pub length: i64,
+    /// Base frame duration in milliseconds
+    #[serde(rename = "frameDuration")]
+    pub frame_duration: i64,
+    /// Per-frame duration multipliers, in percent of `frame_duration`
+    #[serde(rename = "frameDurationMultipliers", default)]
+    pub frame_duration_multipliers: Vec<i64>,
+}
+
+fn default_alpha() -> u8 {
+    255
+}
+
+fn default_blend_mode() -> String {
+    "normal".to_owned()
+}
+
+fn default_parent_index() -> i64 {
+    -1
+}
+
+impl PyxelDoc {
+    /// Parse the content of `docData.json`
+    pub fn from_json(json: &str) -> Result<PyxelDoc> {
+        let de = &mut serde_json::Deserializer::from_str(json);
+        let doc: PyxelDoc = serde_path_to_error::deserialize(de)
+            .map_err(|err| ConvertError::json(DOC_DATA_ENTRY, err))?;
+
+        // values used as divisors by the converter
+        if doc.canvas.tile_width <= 0 {
+            return Err(doc_error("/canvas/tileWidth", "must be greater than 0"));
+        }
+        if doc.canvas.tile_height <= 0 {
+            return Err(doc_error("/canvas/tileHeight", "must be greater than 0"));
+        }
+        if doc.tileset.tiles_wide <= 0 {
+            return Err(doc_error("/tileset/tilesWide", "must be greater than 0"));
+        }
+        Ok(doc)
+    }
+}
+
+fn doc_error(pointer: &str, message: &str) -> ConvertError {
+    ConvertError::Json {
+        entry: DOC_DATA_ENTRY.to_owned(),
+        pointer: pointer.to_owned(),
+        message: message.to_owned(),
+    }
+}
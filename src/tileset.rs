@@ -0,0 +1,423 @@
+use std::io::{Read, Seek};
+use std::collections::{BTreeMap, HashMap};
+use image::{RgbaImage, ImageFormat, imageops};
+use zip::ZipArchive;
+use crate::convert::rotated_tile_indices;
+use crate::error::{ConvertError, Result};
+use crate::layers::is_group;
+use crate::pyxel::{PyxelDoc, TileRef};
+use crate::{ConvertOptions, TileDedupe};
+
+/// LDtk flip bits
+const FLIP_X: i64 = 1;
+const FLIP_Y: i64 = 2;
+
+/// Tile images of a pyxel archive, keyed by tile index ("tileXXX.png")
+pub type TileImages = BTreeMap<i64, RgbaImage>;
+
+/// Layer images of a pyxel archive, keyed by layer index ("layerXXX.png")
+pub type LayerImages = BTreeMap<i64, RgbaImage>;
+
+/// Tileset image geometry, shared between the tileset builder and the LDtk conversion
+pub struct SharedData {
+    pub tileset_filename: String,
+    /// size (width, height) of each tileset image page
+    pub pages: Vec<(i64, i64)>,
+    pub tile_w: i64,
+    pub tile_h: i64,
+    /// number of tiles per row in the tileset image
+    pub tiles_per_row: i64,
+    /// number of tiles per tileset image page
+    pub tiles_per_page: i64,
+    /// distance in pixels from image borders
+    pub padding: i64,
+    /// space in pixels between tiles
+    pub spacing: i64,
+    /// tiles of each converted document, in the same order as the documents
+    pub docs: Vec<DocTiles>,
+}
+
+/// Where the tiles of a pyxel document are in the tileset image
+pub struct DocTiles {
+    /// pyxel tile index -> tile of the tileset image
+    pub tile_ids: HashMap<i64, AtlasTile>,
+    /// pyxel tile index -> tile of its 90° clockwise rotated copy in the tileset image
+    pub rotated_tiles: HashMap<i64, AtlasTile>,
+}
+
+/// A tile of the tileset image, as used to draw a pyxel tile
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AtlasTile {
+    /// tile id in the tileset image
+    pub tile_id: i64,
+    /// LDtk flip bits (X=1, Y=2) turning the stored tile into the pyxel tile
+    pub flip: i64,
+}
+
+impl SharedData {
+    /// Tileset image page of a tile
+    pub fn page_of(&self, tile_id: i64) -> usize {
+        (tile_id / self.tiles_per_page) as usize
+    }
+
+    /// Tile id in its tileset image page
+    pub fn page_tile_id(&self, tile_id: i64) -> i64 {
+        tile_id % self.tiles_per_page
+    }
+
+    /// Pixel coordinates (`[x,y]`) of a tile in its tileset image page
+    pub fn tile_src(&self, tile_id: i64) -> Vec<i64> {
+        let page_tile_id = self.page_tile_id(tile_id);
+        let col = page_tile_id % self.tiles_per_row;
+        let row = page_tile_id / self.tiles_per_row;
+        vec![
+            self.padding + col * (self.tile_w + self.spacing),
+            self.padding + row * (self.tile_h + self.spacing),
+        ]
+    }
+}
+
+/// Name of a tileset image page: the first page keeps the tileset name, the next ones get a number
+pub fn page_name(name: &str, page: usize) -> String {
+    match page {
+        0 => name.to_owned(),
+        _ => format!("{}_{}", name, page + 1),
+    }
+}
+
+/// Path of a tileset image page, the page number goes before the extension
+pub fn page_rel_path(rel_path: &str, page: usize) -> String {
+    let file_start = rel_path.rfind('/').map_or(0, |pos| pos + 1);
+    match rel_path[file_start..].rfind('.') {
+        Some(pos) if page > 0 => page_name(&rel_path[..file_start + pos], page) + &rel_path[file_start + pos..],
+        _ => page_name(rel_path, page),
+    }
+}
+
+// ---------------------------------------
+/// Decode the `tileN.png` files of the pyxel archive
+pub fn read_tile_images<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<TileImages> {
+    read_numbered_images(archive, "tile")
+}
+
+/// Decode the `layerN.png` files of the pyxel archive (the pixels of each layer), keyed by layer index
+pub fn read_layer_images<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<LayerImages> {
+    read_numbered_images(archive, "layer")
+}
+
+/// Decode the "<prefix>XXX.png" files of the pyxel archive, keyed by number
+fn read_numbered_images<R: Read + Seek>(archive: &mut ZipArchive<R>, prefix: &str) -> Result<BTreeMap<i64, RgbaImage>> {
+    let entries: Vec<(i64, String)> = archive
+        .file_names()
+        .filter_map(|name| Some((entry_number(name, prefix)?, name.to_owned())))
+        .collect();
+
+    let mut images = BTreeMap::new();
+    for (index, entry) in entries {
+        let mut file = archive.by_name(&entry).map_err(|err| ConvertError::zip(&entry, err))?;
+        let mut bytes: Vec<u8> = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes).map_err(|err| ConvertError::io(&entry, err))?;
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png)
+            .map_err(|source| ConvertError::Image { entry: entry.to_owned(), source })?;
+        images.insert(index, image.into_rgba8());
+    }
+    Ok(images)
+}
+
+// ---------------------------------------
+/// Turn the painted cells of the layers (cells without tile reference that are not fully
+/// transparent in `layerN.png`) into tile references. Cells identical to a tile use it,
+/// the other ones are added as new tiles after the pyxel ones.
+/// Returns the number of new tiles.
+pub fn slice_pixel_layers(doc: &mut PyxelDoc, tile_images: &mut TileImages, layer_images: &LayerImages) -> usize {
+    let tile_w = doc.canvas.tile_width as u32;
+    let tile_h = doc.canvas.tile_height as u32;
+    let map_w = doc.canvas.width / tile_w as i64;
+    let map_h = doc.canvas.height / tile_h as i64;
+
+    // pixels -> tile index
+    let mut known: HashMap<Vec<u8>, i64> = HashMap::new();
+    for (&index, tile_image) in tile_images.iter() {
+        known.entry(tile_image.as_raw().to_owned()).or_insert(index);
+    }
+    let first_new = tile_images.keys().last().map_or(0, |&index| index + 1).max(doc.tileset.num_tiles as i64);
+    let mut next_index = first_new;
+
+    let groups: Vec<usize> = doc.canvas.layers.keys().cloned().filter(|&index| is_group(&doc.canvas.layers, index)).collect();
+    for (&index, layer) in doc.canvas.layers.iter_mut() {
+        let image = match layer_images.get(&(index as i64)) {
+            Some(image) if !groups.contains(&index) => image,
+            _ => continue,
+        };
+        for cell_y in 0..map_h {
+            for cell_x in 0..map_w {
+                let cell = cell_y * map_w + cell_x;
+                let (x, y) = (cell_x as u32 * tile_w, cell_y as u32 * tile_h);
+                if layer.tile_refs.contains_key(&cell) || x + tile_w > image.width() || y + tile_h > image.height() {
+                    continue;
+                }
+                let pixels = imageops::crop_imm(image, x, y, tile_w, tile_h).to_image();
+                if pixels.pixels().all(|pixel| pixel[3] == 0) {
+                    continue;
+                }
+                let tile_index = *known.entry(pixels.as_raw().to_owned()).or_insert_with(|| {
+                    tile_images.insert(next_index, pixels);
+                    next_index += 1;
+                    next_index - 1
+                });
+                layer.tile_refs.insert(cell, TileRef { index: tile_index, flip_x: false, rot: 0 });
+            }
+        }
+    }
+
+    let new_tiles = (next_index - first_new) as usize;
+    doc.tileset.num_tiles += new_tiles;
+    new_tiles
+}
+
+// ---------------------------------------
+/// Build a single tileset image from the tiles of one or more pyxel documents.
+/// Duplicated tiles are stored only once, according to `dedupe`.
+/// Returns one image per page when the tileset is split (see `ConvertOptions::split_atlas`).
+pub fn build_tileset_image(
+    docs: &[(&PyxelDoc, &TileImages)],
+    tileset_name: &str,
+    dedupe: TileDedupe,
+    options: &ConvertOptions,
+    warnings: &mut Vec<String>,
+) -> Result<(Vec<RgbaImage>, SharedData)> {
+    let (first_doc, _) = docs.first().ok_or_else(|| ConvertError::unsupported("no document to convert"))?;
+
+    // get tile width and height from Pyxel Edit (json)
+    let tiles_per_row = first_doc.tileset.tiles_wide as u64;
+    let tile_w = first_doc.canvas.tile_width as u64;
+    let tile_h = first_doc.canvas.tile_height as u64;
+
+    // [1] COLLECT tiles of every document
+    let mut atlas = TileCollector::new(dedupe);
+    let mut doc_tiles: Vec<DocTiles> = vec![];
+    for (doc, tile_images) in docs.iter() {
+        if doc.canvas.tile_width as u64 != tile_w || doc.canvas.tile_height as u64 != tile_h {
+            return Err(ConvertError::unsupported(format!(
+                "'{}' uses {}x{} tiles, '{}' uses {}x{} tiles (a single tileset needs the same tile size)",
+                doc.name, doc.canvas.tile_width, doc.canvas.tile_height, first_doc.name, tile_w, tile_h
+            )));
+        }
+
+        let mut tile_ids: HashMap<i64, AtlasTile> = HashMap::new();
+        for (&index, tile_image) in tile_images.iter() {
+            tile_ids.insert(index, atlas.add(tile_image));
+        }
+
+        // tiles used with a 90° or 270° rotation need an extra rotated copy,
+        // appended after the pyxel tiles (LDtk can only flip tiles)
+        let mut rotated_tiles: HashMap<i64, AtlasTile> = HashMap::new();
+        let rotated_indices = rotated_tile_indices(doc);
+        if tile_w == tile_h {
+            for index in rotated_indices {
+                let tile_image = tile_images
+                    .get(&index)
+                    .ok_or_else(|| ConvertError::MissingEntry { entry: format!("tile{}.png", index) })?;
+                rotated_tiles.insert(index, atlas.add(&imageops::rotate90(tile_image)));
+            }
+        } else if !rotated_indices.is_empty() {
+            warnings.push(format!("'{}': 90° tile rotations ignored (tiles are not square)", doc.name));
+        }
+
+        doc_tiles.push(DocTiles { tile_ids, rotated_tiles });
+    }
+
+    // [2] CREATE DEST image
+    // extruded edges are drawn in the padding and the spacing
+    let extrude = options.extrude as u64;
+    let padding = options.padding as u64 + extrude;
+    let spacing = options.spacing as u64 + 2 * extrude;
+    let grid = AtlasGrid { tile_w, tile_h, padding, spacing };
+    let num_tiles = atlas.tiles.len() as u64;
+    let (tiles_per_row, tiles_per_page) = grid.choose_layout(num_tiles, tiles_per_row, options).ok_or_else(|| {
+        let hint = if options.split_atlas { "" } else { " (enable atlas splitting to write several images)" };
+        ConvertError::unsupported(format!(
+            "{} tiles of {}x{} px don't fit in a tileset image of at most {} px{}",
+            num_tiles, tile_w, tile_h, options.max_atlas_size.unwrap_or(0), hint
+        ))
+    })?;
+    let num_pages = num_tiles.div_ceil(tiles_per_page).max(1);
+    let pages: Vec<(i64, i64)> = (0..num_pages)
+        .map(|page| {
+            let page_tiles = (num_tiles - page * tiles_per_page).min(tiles_per_page);
+            let (w, h) = grid.size(page_tiles, tiles_per_row, options.power_of_two);
+            (w as i64, h as i64)
+        })
+        .collect();
+    let mut dest_imgs: Vec<RgbaImage> = pages.iter().map(|&(w, h)| RgbaImage::new(w as u32, h as u32)).collect();
+
+    let data = SharedData {
+        tileset_filename: tileset_name.to_owned(),
+        pages,
+        tile_w: tile_w as i64,
+        tile_h: tile_h as i64,
+        tiles_per_row: tiles_per_row as i64,
+        tiles_per_page: tiles_per_page as i64,
+        padding: padding as i64,
+        spacing: spacing as i64,
+        docs: doc_tiles,
+    };
+
+    // [3] COPY tiles into the tileset image
+    for (ti, curr_tile) in atlas.tiles.iter().enumerate() {
+        let src = data.tile_src(ti as i64);
+        let cell = (src[0] as u32, src[1] as u32, tile_w as u32, tile_h as u32);
+        draw_tile(&mut dest_imgs[data.page_of(ti as i64)], curr_tile, cell, extrude as u32);
+    }
+
+    Ok((dest_imgs, data))
+}
+
+/// Placement of the tiles in the tileset image
+struct AtlasGrid {
+    tile_w: u64,
+    tile_h: u64,
+    padding: u64,
+    spacing: u64,
+}
+
+impl AtlasGrid {
+    /// Image size (width, height) holding `num_tiles` tiles with `tiles_per_row` tiles per row
+    fn size(&self, num_tiles: u64, tiles_per_row: u64, pot: bool) -> (u64, u64) {
+        let num_rows = num_tiles.div_ceil(tiles_per_row).max(1);
+        let w = 2 * self.padding + tiles_per_row * self.tile_w + (tiles_per_row - 1) * self.spacing;
+        let h = 2 * self.padding + num_rows * self.tile_h + (num_rows - 1) * self.spacing;
+        if pot {
+            (w.next_power_of_two(), h.next_power_of_two())
+        } else {
+            (w, h)
+        }
+    }
+
+    /// Number of tiles in a row of an image `width` pixels wide, as LDtk computes it
+    fn columns(&self, width: u64) -> u64 {
+        (width - 2 * self.padding + self.spacing) / (self.tile_w + self.spacing)
+    }
+
+    /// Choose the number of tiles per row and per page (tiles per row, tiles per page).
+    /// The pyxel tileset width is kept unless a power-of-two or a maximum size is asked,
+    /// None if the tiles don't fit in the maximum size.
+    fn choose_layout(&self, num_tiles: u64, tiles_wide: u64, options: &ConvertOptions) -> Option<(u64, u64)> {
+        let pot = options.power_of_two;
+        let fits = |(w, h): (u64, u64)| options.max_atlas_size.is_none_or(|max| w <= max as u64 && h <= max as u64);
+        let all_tiles = num_tiles.max(1);
+
+        if !pot && fits(self.size(num_tiles, tiles_wide, pot)) {
+            return Some((tiles_wide, all_tiles));
+        }
+        // smallest image, then the squarest one. A power-of-two width may hold more tiles per
+        // row than asked: the rows are filled, so that tile ids match the LDtk columns
+        let single_page = (1..=all_tiles)
+            .map(|tiles_per_row| self.size(num_tiles, tiles_per_row, pot))
+            .filter(|&size| fits(size))
+            .min_by_key(|&(w, h)| (w * h, w.max(h)))
+            .map(|(w, _)| (self.columns(w), all_tiles));
+        if single_page.is_some() || !options.split_atlas {
+            return single_page;
+        }
+
+        // pages as full as possible: the widest row, then as many rows as fit
+        let tiles_per_row = (1..=all_tiles).take_while(|&n| fits(self.size(n, n, pot))).last()?;
+        let num_rows = (1..).take_while(|&rows| fits(self.size(tiles_per_row * rows, tiles_per_row, pot))).last()?;
+        let (w, _) = self.size(tiles_per_row * num_rows, tiles_per_row, pot);
+        let tiles_per_row = self.columns(w);
+        Some((tiles_per_row, tiles_per_row * num_rows))
+    }
+}
+
+/// Copy a tile in the `w x h` cell at `x,y` and repeat its edge pixels `extrude` times around it
+fn draw_tile(dest: &mut RgbaImage, tile: &RgbaImage, (x, y, w, h): (u32, u32, u32, u32), extrude: u32) {
+    for cy in 0..h + 2 * extrude {
+        for cx in 0..w + 2 * extrude {
+            // nearest cell pixel, tiles smaller than the cell are left transparent
+            let px = cx.saturating_sub(extrude).min(w - 1);
+            let py = cy.saturating_sub(extrude).min(h - 1);
+            if px < tile.width() && py < tile.height() {
+                dest.put_pixel(x + cx - extrude, y + cy - extrude, *tile.get_pixel(px, py));
+            }
+        }
+    }
+}
+
+/// Tiles of the tileset image, in tile id order
+struct TileCollector {
+    tiles: Vec<RgbaImage>,
+    /// pixels -> tile id, when deduplicating
+    known: Option<HashMap<Vec<u8>, i64>>,
+    /// also look for flipped copies of the known tiles
+    flipped: bool,
+}
+
+impl TileCollector {
+    fn new(dedupe: TileDedupe) -> TileCollector {
+        TileCollector {
+            tiles: vec![],
+            known: if dedupe == TileDedupe::None { None } else { Some(HashMap::new()) },
+            flipped: dedupe == TileDedupe::Flipped,
+        }
+    }
+
+    /// Add a tile, returns where it is in the tileset image
+    fn add(&mut self, tile: &RgbaImage) -> AtlasTile {
+        let tile_id = self.tiles.len() as i64;
+        if let Some(known) = &mut self.known {
+            if let Some(&known_id) = known.get(tile.as_raw()) {
+                return AtlasTile { tile_id: known_id, flip: 0 };
+            }
+            if self.flipped {
+                // flipping the tile gives a known tile, so flipping the known tile gives this one
+                let flips = [
+                    (FLIP_X, imageops::flip_horizontal(tile)),
+                    (FLIP_Y, imageops::flip_vertical(tile)),
+                    (FLIP_X | FLIP_Y, imageops::rotate180(tile)),
+                ];
+                for (flip, flipped_tile) in flips.iter() {
+                    if let Some(&known_id) = known.get(flipped_tile.as_raw()) {
+                        return AtlasTile { tile_id: known_id, flip: *flip };
+                    }
+                }
+            }
+            known.insert(tile.as_raw().to_owned(), tile_id);
+        }
+        self.tiles.push(tile.to_owned());
+        AtlasTile { tile_id, flip: 0 }
+    }
+}
+
+/// Number of a "<prefix>XXX.png" archive entry
+fn entry_number(name: &str, prefix: &str) -> Option<i64> {
+    name.strip_prefix(prefix)?.strip_suffix(".png")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tiles per row fill the image width, whatever the grid and size constraints
+    #[test]
+    fn layout_rows_fill_the_image_width() {
+        for &(padding, spacing) in [(0, 0), (1, 0), (0, 2), (1, 2), (3, 1)].iter() {
+            let grid = AtlasGrid { tile_w: 16, tile_h: 16, padding, spacing };
+            for &num_tiles in [1, 3, 50, 130, 514].iter() {
+                for &(pot, max_atlas_size, split_atlas) in
+                    [(false, None, false), (true, None, false), (true, Some(256), true), (false, Some(200), true)].iter()
+                {
+                    let options = ConvertOptions { power_of_two: pot, max_atlas_size, split_atlas, ..Default::default() };
+                    let (tiles_per_row, tiles_per_page) = match grid.choose_layout(num_tiles, 25, &options) {
+                        Some(layout) => layout,
+                        None => continue,
+                    };
+                    let page_tiles = tiles_per_page.min(num_tiles);
+                    let (w, h) = grid.size(page_tiles, tiles_per_row, pot);
+                    assert_eq!(grid.columns(w), tiles_per_row, "{:?}", (padding, spacing, num_tiles, pot));
+                    assert!(max_atlas_size.is_none_or(|max| w <= max as u64 && h <= max as u64));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+// Unique identifiers of the generated LDtk project.
+//
+// LDtk uses a single uid space for every definition and level (tilesets, layers, levels,
+// entities, enums, fields...), and stores the next free one in `Ldtk.next_uid`.
+
+/// Hands out unique uids for a LDtk project
+pub struct UidAllocator {
+    next: i64,
+}
+
+impl UidAllocator {
+    /// Start allocating from `first` (for a new project, or the `next_uid` of an existing one)
+    pub fn new(first: i64) -> UidAllocator {
+        UidAllocator { next: first }
+    }
+
+    /// Allocate a new uid
+    pub fn alloc(&mut self) -> i64 {
+        let uid = self.next;
+        self.next += 1;
+        uid
+    }
+
+    /// Next available uid, to be written into `Ldtk.next_uid`
+    pub fn next_uid(&self) -> i64 {
+        self.next
+    }
+}
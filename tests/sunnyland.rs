@@ -0,0 +1,284 @@
+// Regression tests converting the sample document.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use pyxeledit2ldtk::{CollisionMap, ConversionOutput, ConvertOptions, Converter, EntityMap, LayerGroups, LayerSettings};
+use pyxeledit2ldtk::ldtk::{EntityDefinition, EnumDefinition, Ldtk, Type};
+use pyxeledit2ldtk::ldtk::LayerInstance;
+use serde_json::{json, Value};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+const SAMPLE: &str = "resources/sunnyland-01.pyxel";
+
+fn convert_sample(options: ConvertOptions) -> ConversionOutput {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SAMPLE);
+    Converter::new(options).convert_file(&path).expect("sample conversion")
+}
+
+/// The sample archive with its `docData.json` changed by `edit`
+fn edited_sample(edit: impl Fn(&mut Value)) -> Cursor<Vec<u8>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SAMPLE);
+    let mut archive = ZipArchive::new(File::open(path).expect("sample")).expect("sample archive");
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).expect("sample entry");
+        let mut content = vec![];
+        entry.read_to_end(&mut content).expect("sample entry content");
+        if entry.name() == "docData.json" {
+            let mut doc: Value = serde_json::from_slice(&content).expect("sample docData");
+            edit(&mut doc);
+            content = serde_json::to_vec(&doc).unwrap();
+        }
+        writer.start_file(entry.name(), FileOptions::default()).unwrap();
+        writer.write_all(&content).unwrap();
+    }
+    let mut cursor = writer.finish().unwrap();
+    cursor.set_position(0);
+    cursor
+}
+
+/// Sample with its `front` layer renamed `name` and put in a `grp` group
+fn grouped_sample(name: &str) -> Cursor<Vec<u8>> {
+    edited_sample(|doc| {
+        let layers = &mut doc["canvas"]["layers"];
+        let back = layers["1"].take();
+        layers["0"]["name"] = json!(name);
+        layers["0"]["parentIndex"] = json!(1);
+        layers["1"] = json!({
+            "name": "grp", "type": "group", "parentIndex": -1, "alpha": 255, "hidden": false, "muted": false,
+            "soloed": false, "collapsed": false, "blendMode": "normal", "tileRefs": {}
+        });
+        layers["2"] = back;
+        doc["canvas"]["numLayers"] = json!(3);
+    })
+}
+
+/// Layer instances of the first level
+fn layer_instances(output: &ConversionOutput) -> &[LayerInstance] {
+    output.ldtk.levels[0].layer_instances.as_deref().expect("layer instances")
+}
+
+/// Every tile `src` is where LDtk expects the tile `t`: LDtk computes the tileset columns
+/// from its width, padding and spacing
+fn check_tile_src(output: &ConversionOutput) {
+    let mut checked = 0;
+    for level in output.ldtk.levels.iter() {
+        for layer in level.layer_instances.iter().flatten() {
+            let tileset_uid = match layer.tileset_def_uid {
+                Some(uid) => uid,
+                None => continue,
+            };
+            let tileset = output.ldtk.defs.tilesets.iter().find(|def| def.uid == tileset_uid).expect("layer tileset");
+            let grid = tileset.tile_grid_size;
+            let cols = (tileset.px_wid - 2 * tileset.padding + tileset.spacing) / (grid + tileset.spacing);
+            let rows = (tileset.px_hei - 2 * tileset.padding + tileset.spacing) / (grid + tileset.spacing);
+            for tile in layer.grid_tiles.iter().chain(layer.auto_layer_tiles.iter()) {
+                let expected = vec![
+                    tileset.padding + (tile.t % cols) * (grid + tileset.spacing),
+                    tileset.padding + (tile.t / cols) * (grid + tileset.spacing),
+                ];
+                assert_eq!(tile.src, expected, "tile {} of layer '{}'", tile.t, layer.identifier);
+                assert!(tile.t < cols * rows, "tile {} out of the tileset", tile.t);
+                checked += 1;
+            }
+        }
+    }
+    assert!(checked > 0, "no tile converted");
+}
+
+#[test]
+fn tile_src_default() {
+    check_tile_src(&convert_sample(ConvertOptions::default()));
+}
+
+#[test]
+fn tile_src_padding_spacing() {
+    let options = ConvertOptions { padding: 1, spacing: 2, ..ConvertOptions::default() };
+    check_tile_src(&convert_sample(options));
+}
+
+#[test]
+fn tile_src_power_of_two() {
+    let options = ConvertOptions { padding: 1, power_of_two: true, ..ConvertOptions::default() };
+    check_tile_src(&convert_sample(options));
+    let options = ConvertOptions { padding: 1, spacing: 2, extrude: 1, power_of_two: true, ..ConvertOptions::default() };
+    check_tile_src(&convert_sample(options));
+}
+
+#[test]
+fn grouped_entity_layer() {
+    let entities = EntityMap { tiles: (0..1000).map(|index| (index, "Thing".to_owned())).collect(), ..EntityMap::default() };
+    for (groups, source_layer) in [(LayerGroups::Flatten, "entities"), (LayerGroups::Prefix, "grp/entities")] {
+        let entities = EntityMap { source_layer: source_layer.to_owned(), ..entities.clone() };
+        let options = ConvertOptions { layer_groups: groups, entities: Some(entities), ..ConvertOptions::default() };
+        let output = Converter::new(options).convert(grouped_sample("entities")).expect("conversion");
+        let layers = layer_instances(&output);
+        let identifiers: Vec<&str> = layers.iter().map(|layer| layer.identifier.as_str()).collect();
+        assert_eq!(identifiers, ["Entities", "back"], "{:?}", groups);
+        assert_eq!(layers[0].entity_instances.len(), 231, "{:?}", groups);
+    }
+}
+
+#[test]
+fn grouped_collision_layer() {
+    for (groups, source_layer) in [(LayerGroups::Flatten, "walls"), (LayerGroups::Prefix, "grp/walls")] {
+        let collision = CollisionMap { source_layer: Some(source_layer.to_owned()), ..CollisionMap::default() };
+        let options = ConvertOptions { layer_groups: groups, collision: Some(collision), ..ConvertOptions::default() };
+        let output = Converter::new(options).convert(grouped_sample("walls")).expect("conversion");
+        let layers = layer_instances(&output);
+        let identifiers: Vec<&str> = layers.iter().map(|layer| layer.identifier.as_str()).collect();
+        assert_eq!(identifiers, ["Collisions", "back"], "{:?}", groups);
+        assert_eq!(layers[0].int_grid_csv.iter().filter(|&&value| value == 1).count(), 231, "{:?}", groups);
+    }
+}
+
+#[test]
+fn grouped_layer_settings() {
+    let walls = LayerSettings { layer_type: Some(Type::IntGrid), ..LayerSettings::default() };
+    let layers = vec![("grp/walls".to_owned(), walls.clone())].into_iter().collect();
+    let options = ConvertOptions { layer_groups: LayerGroups::Prefix, layers, ..ConvertOptions::default() };
+    let output = Converter::new(options).convert(grouped_sample("walls")).expect("conversion");
+    let layers = layer_instances(&output);
+    let identifiers: Vec<&str> = layers.iter().map(|layer| layer.identifier.as_str()).collect();
+    assert_eq!(identifiers, ["grp/walls", "back"]);
+    assert_eq!(layers[0].int_grid_csv.iter().filter(|&&value| value == 1).count(), 231);
+
+    // the pyxel name of a layer in a group is not the name of the converted layer
+    let layers = vec![("walls".to_owned(), walls)].into_iter().collect();
+    let options = ConvertOptions { layer_groups: LayerGroups::Prefix, layers, ..ConvertOptions::default() };
+    let output = Converter::new(options).convert(grouped_sample("walls")).expect("conversion");
+    assert!(output.warnings.iter().any(|warning| warning.contains("'walls'")), "{:?}", output.warnings);
+}
+
+#[test]
+fn merge_split_atlas() {
+    let split = |size| ConvertOptions { max_atlas_size: Some(size), split_atlas: true, ..ConvertOptions::default() };
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SAMPLE);
+    let first = convert_sample(split(128));
+    assert_eq!(first.tilesets.len(), 8);
+    let output = Converter::new(split(256)).merge_into(first.ldtk).convert_file(&path).expect("merged conversion");
+    check_tile_src(&output);
+
+    let tilesets: Vec<&str> = output.ldtk.defs.tilesets.iter().map(|def| def.identifier.as_str()).collect();
+    assert_eq!(tilesets, ["sunnyland-01", "sunnyland-01_2"]);
+    let identifiers: Vec<&str> = layer_instances(&output).iter().map(|layer| layer.identifier.as_str()).collect();
+    assert_eq!(identifiers, ["front", "front_2", "back", "back_2"]);
+    let tiles: usize = layer_instances(&output).iter().map(|layer| layer.grid_tiles.len()).sum();
+    assert_eq!(tiles, 231 + 283);
+}
+
+#[test]
+fn palette_int_grid_slots() {
+    let collision = CollisionMap { tiles: vec![(0, 13)].into_iter().collect(), ..CollisionMap::default() };
+    let palette_int_grid = Some("Palette".to_owned());
+    let options = ConvertOptions { palette_int_grid, collision: Some(collision), ..ConvertOptions::default() };
+    let output = convert_sample(options);
+    let value_color = |layer: &str, value: i64| {
+        let layer_def = output.ldtk.defs.layers.iter().find(|def| def.identifier == layer).expect("IntGrid layer");
+        layer_def.int_grid_values.iter().find(|def| def.value == value).map(|def| def.color.to_owned())
+    };
+    // slots 9 to 12 of the sample palette are empty
+    assert_eq!(value_color("Palette", 8).as_deref(), Some("#eb8931"));
+    assert_eq!(value_color("Palette", 9), None);
+    assert_eq!(value_color("Palette", 13).as_deref(), Some("#f7e26b"));
+    assert_eq!(value_color("Collisions", 13).as_deref(), Some("#f7e26b"));
+}
+
+#[test]
+fn animation_enum_uid() {
+    let animated = || {
+        edited_sample(|doc| {
+            doc["animations"]["0"] =
+                json!({ "name": "coin", "baseTile": 2, "length": 3, "frameDuration": 100, "frameDurationMultipliers": [] });
+        })
+    };
+    let options = ConvertOptions { animation_enum: Some("Animations".to_owned()), ..ConvertOptions::default() };
+    let output = Converter::new(options.clone()).convert(animated()).expect("conversion");
+    let ldtk = &output.ldtk;
+    assert_eq!(ldtk.defs.enums.len(), 1);
+    let enum_uid = ldtk.defs.enums[0].uid;
+    let other_uids = ldtk.defs.tilesets.iter().map(|def| def.uid).chain(ldtk.defs.layers.iter().map(|def| def.uid));
+    assert!(other_uids.chain(ldtk.levels.iter().map(|level| level.uid)).all(|uid| uid != enum_uid));
+    assert!(enum_uid < ldtk.next_uid);
+
+    // merged again: same enum, no new uid
+    let merged = Converter::new(options).merge_into(output.ldtk.clone()).convert(animated()).expect("merged conversion");
+    assert_eq!(merged.ldtk.defs.enums.len(), 1);
+    assert_eq!(merged.ldtk.defs.enums[0].uid, enum_uid);
+    assert_eq!(merged.ldtk.next_uid, output.ldtk.next_uid);
+}
+
+/// Uids of the definitions and levels of a project
+fn project_uids(ldtk: &Ldtk) -> Vec<i64> {
+    let defs = &ldtk.defs;
+    let mut uids: Vec<i64> = defs.tilesets.iter().map(|def| def.uid).collect();
+    uids.extend(defs.layers.iter().map(|def| def.uid));
+    uids.extend(defs.entities.iter().map(|def| def.uid));
+    uids.extend(defs.enums.iter().map(|def| def.uid));
+    uids.extend(ldtk.levels.iter().map(|level| level.uid));
+    uids
+}
+
+#[test]
+fn merge_into_project() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SAMPLE);
+    let entities = EntityMap { tiles: vec![(300, "Coin".to_owned())].into_iter().collect(), ..EntityMap::default() };
+    let options = ConvertOptions { entities: Some(entities), ..ConvertOptions::default() };
+    let mut base = convert_sample(options.clone()).ldtk;
+
+    // made in LDtk: a level, a layer, an enum and a field of an entity
+    let mut uid = base.next_uid;
+    let mut next_uid = || {
+        uid += 1;
+        uid - 1
+    };
+    let mut level = base.levels[0].clone();
+    level.identifier = "Hand made".to_owned();
+    level.uid = next_uid();
+    level.world_x = 1000;
+    level.layer_instances = Some(vec![]);
+    base.levels.push(level);
+    let mut layer_def = base.defs.layers.iter().find(|def| def.identifier == "back").unwrap().clone();
+    layer_def.identifier = "notes".to_owned();
+    layer_def.uid = next_uid();
+    base.defs.layers.push(layer_def);
+    base.defs.enums.push(EnumDefinition {
+        external_file_checksum: None,
+        external_rel_path: None,
+        icon_tileset_uid: None,
+        identifier: "Items".to_owned(),
+        uid: next_uid(),
+        values: vec![],
+    });
+    let coin: &mut EntityDefinition = base.defs.entities.iter_mut().find(|def| def.identifier == "Coin").unwrap();
+    coin.color = "#ff0000".to_owned();
+    base.next_uid = next_uid();
+    let base_uids = project_uids(&base);
+
+    // the hand made parts are kept, the level gets an instance of each layer
+    let merged = Converter::new(options.clone()).merge_into(base.clone()).convert_file(&path).expect("merge");
+    let ldtk = &merged.ldtk;
+    assert_eq!(project_uids(ldtk), base_uids);
+    assert_eq!(ldtk.next_uid, base.next_uid);
+    let levels: Vec<&str> = ldtk.levels.iter().map(|level| level.identifier.as_str()).collect();
+    assert_eq!(levels, ["sunnyland-01", "Hand made"]);
+    let layers: Vec<&str> = ldtk.defs.layers.iter().map(|def| def.identifier.as_str()).collect();
+    assert_eq!(layers, ["Entities", "front", "back", "notes"]);
+    assert_eq!(ldtk.defs.enums[0].identifier, "Items");
+    assert_eq!(ldtk.defs.entities[0].color, "#ff0000");
+    assert_eq!(ldtk.levels[1].layer_instances.as_ref().unwrap().len(), 4);
+
+    // converting the same document again changes nothing
+    let again = Converter::new(options.clone()).merge_into(ldtk.clone()).convert_file(&path).expect("merge");
+    assert_eq!(serde_json::to_value(&again.ldtk).unwrap(), serde_json::to_value(ldtk).unwrap());
+
+    // new definitions get uids from the project `next_uid`
+    let options = ConvertOptions { palette_int_grid: Some("Palette".to_owned()), ..options };
+    let merged = Converter::new(options).merge_into(base.clone()).convert_file(&path).expect("merge");
+    let palette_def = merged.ldtk.defs.layers.iter().find(|def| def.identifier == "Palette").expect("new layer");
+    assert_eq!(palette_def.uid, base.next_uid);
+    assert_eq!(merged.ldtk.next_uid, base.next_uid + 1);
+    assert_eq!(merged.ldtk.levels[1].layer_instances.as_ref().unwrap().len(), 5);
+}